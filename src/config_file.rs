@@ -0,0 +1,350 @@
+use chrono::{Local, NaiveDateTime};
+use jellyfin_rpc::prelude::config::Config;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Why the config file couldn't be used, with enough detail to point at the problem.
+#[derive(Debug, Clone)]
+pub struct ConfigError {
+    pub path: String,
+    pub message: String,
+    /// 1-based, 0 when the error isn't tied to a position in the file
+    pub line: usize,
+    pub column: usize,
+    pub raw: String,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            0 => write!(f, "{}: {}", self.path, self.message),
+            line => write!(
+                f,
+                "{}:{}:{}: {}",
+                self.path, line, self.column, self.message
+            ),
+        }
+    }
+}
+
+/// Loads the config, a missing file counts as the default config but a broken
+/// one is reported instead of being replaced.
+pub fn load(config_path: &str) -> Result<Config, ConfigError> {
+    let raw = match std::fs::read_to_string(config_path) {
+        Ok(raw) => raw,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+        Err(err) => {
+            return Err(ConfigError {
+                path: config_path.to_string(),
+                message: err.to_string(),
+                line: 0,
+                column: 0,
+                raw: String::new(),
+            })
+        }
+    };
+
+    // Parse it ourselves first since serde_json knows where things went wrong
+    if let Err(err) = serde_json::from_str::<Config>(&raw) {
+        return Err(ConfigError {
+            path: config_path.to_string(),
+            message: err.to_string(),
+            line: err.line(),
+            column: err.column(),
+            raw,
+        });
+    }
+
+    Config::load(config_path).map_err(|err| ConfigError {
+        path: config_path.to_string(),
+        message: format!("{:?}", err),
+        line: 0,
+        column: 0,
+        raw,
+    })
+}
+
+/// How many backups are kept next to the config file, older ones get deleted.
+const BACKUPS: usize = 10;
+
+/// Saving twice in the same second shouldn't cost a backup. Reading it back
+/// still works for the backups older versions made without the fraction.
+const BACKUP_TIMESTAMP: &str = "%Y%m%d-%H%M%S%.6f";
+const BACKUP_TIMESTAMP_PARSE: &str = "%Y%m%d-%H%M%S%.f";
+
+/// A copy of the config file taken right before it was overwritten.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Backup {
+    pub path: PathBuf,
+    pub timestamp: NaiveDateTime,
+}
+
+impl std::fmt::Display for Backup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.timestamp.format("%Y-%m-%d %H:%M:%S"))
+    }
+}
+
+/// Replaces the config file without ever leaving a half written one behind,
+/// backing up the previous version first.
+pub fn write(config_path: &str, contents: &str) -> io::Result<()> {
+    let path = Path::new(config_path);
+
+    match std::fs::read_to_string(path) {
+        Ok(previous) if previous != contents => backup(path)?,
+        Ok(_) => (),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err),
+    }
+
+    // Same directory as the config, a rename across filesystems wouldn't be atomic
+    let temp = path.with_file_name(format!(".{}.tmp", file_name(path)));
+
+    // A leftover from a crash would keep whatever mode it had
+    match std::fs::remove_file(&temp) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => (),
+    }
+
+    let mut file = create_private(&temp)?;
+
+    // The file might hold secrets somebody already locked down, keep it that way
+    // before anything is written to it
+    match std::fs::metadata(path) {
+        Ok(metadata) => file.set_permissions(metadata.permissions())?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err),
+    }
+
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    drop(file);
+
+    std::fs::rename(&temp, path)
+}
+
+/// Only we can read it until it's given the permissions of the file it replaces,
+/// a new config keeps it that way since it holds the API key.
+#[cfg(unix)]
+fn create_private(path: &Path) -> io::Result<std::fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn create_private(path: &Path) -> io::Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+}
+
+/// Lists the backups of the config file, newest first.
+pub fn backups(config_path: &str) -> Vec<Backup> {
+    let path = Path::new(config_path);
+    let prefix = format!("{}.", file_name(path));
+
+    let entries = match std::fs::read_dir(dir(path)) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut backups: Vec<Backup> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().into_string().ok()?;
+            let timestamp = name.strip_prefix(&prefix)?.strip_suffix(".bak")?;
+
+            Some(Backup {
+                path: entry.path(),
+                timestamp: NaiveDateTime::parse_from_str(timestamp, BACKUP_TIMESTAMP_PARSE).ok()?,
+            })
+        })
+        .collect();
+
+    backups.sort_by_key(|backup| std::cmp::Reverse(backup.timestamp));
+    backups
+}
+
+/// Puts a backup back in place, the config it replaces gets backed up as well.
+pub fn restore(config_path: &str, backup: &Backup) -> io::Result<()> {
+    write(config_path, &std::fs::read_to_string(&backup.path)?)
+}
+
+fn backup(path: &Path) -> io::Result<()> {
+    let mut timestamp = Local::now().naive_local();
+
+    // The clock can be coarse or go backwards, never overwrite an existing backup
+    let backup = loop {
+        let backup = dir(path).join(format!(
+            "{}.{}.bak",
+            file_name(path),
+            timestamp.format(BACKUP_TIMESTAMP)
+        ));
+        match backup.exists() {
+            true => timestamp += chrono::Duration::microseconds(1),
+            false => break backup,
+        }
+    };
+    std::fs::copy(path, backup)?;
+
+    for old in backups(&path.to_string_lossy()).iter().skip(BACKUPS) {
+        std::fs::remove_file(&old.path).ok();
+    }
+
+    Ok(())
+}
+
+fn dir(path: &Path) -> &Path {
+    path.parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for each test, removed again afterwards.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "jellyfin-rpc-iced-{}-{}",
+                name,
+                std::process::id()
+            ));
+            std::fs::remove_dir_all(&dir).ok();
+            std::fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+
+        fn config(&self) -> String {
+            self.0.join("main.json").to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn read(path: impl AsRef<Path>) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn write_replaces_the_file_and_cleans_up() {
+        let dir = TempDir::new("write");
+        let config = dir.config();
+
+        write(&config, "first").unwrap();
+        assert_eq!(read(&config), "first");
+        assert!(backups(&config).is_empty());
+
+        write(&config, "second").unwrap();
+        assert_eq!(read(&config), "second");
+
+        let files = std::fs::read_dir(&dir.0).unwrap().count();
+        assert_eq!(files, 2, "only the config and one backup should be left");
+    }
+
+    #[test]
+    fn unchanged_contents_are_not_backed_up() {
+        let dir = TempDir::new("unchanged");
+        let config = dir.config();
+
+        write(&config, "same").unwrap();
+        write(&config, "same").unwrap();
+
+        assert!(backups(&config).is_empty());
+    }
+
+    #[test]
+    fn backups_are_newest_first_even_within_a_second() {
+        let dir = TempDir::new("ordering");
+        let config = dir.config();
+
+        for version in 0..4 {
+            write(&config, &version.to_string()).unwrap();
+        }
+
+        let backups = backups(&config);
+        let contents: Vec<String> = backups.iter().map(|backup| read(&backup.path)).collect();
+        assert_eq!(contents, vec!["2", "1", "0"]);
+    }
+
+    #[test]
+    fn old_backups_are_rotated_out() {
+        let dir = TempDir::new("rotation");
+        let config = dir.config();
+
+        for version in 0..BACKUPS + 5 {
+            write(&config, &version.to_string()).unwrap();
+        }
+
+        let backups = backups(&config);
+        assert_eq!(backups.len(), BACKUPS);
+        assert_eq!(read(&backups[0].path), (BACKUPS + 3).to_string());
+        assert_eq!(read(&backups[BACKUPS - 1].path), "4");
+    }
+
+    #[test]
+    fn backups_without_a_fraction_still_count() {
+        let dir = TempDir::new("legacy");
+        let config = dir.config();
+
+        std::fs::write(dir.0.join("main.json.20240101-120000.bak"), "legacy").unwrap();
+        write(&config, "first").unwrap();
+        write(&config, "second").unwrap();
+
+        let backups = backups(&config);
+        assert_eq!(backups.len(), 2);
+        assert_eq!(read(&backups[1].path), "legacy");
+    }
+
+    #[test]
+    fn restore_puts_the_backup_back_and_keeps_the_current_one() {
+        let dir = TempDir::new("restore");
+        let config = dir.config();
+
+        write(&config, "good").unwrap();
+        write(&config, "broken").unwrap();
+
+        let good = backups(&config)[0].clone();
+        restore(&config, &good).unwrap();
+
+        assert_eq!(read(&config), "good");
+        assert_eq!(read(&backups(&config)[0].path), "broken");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_keeps_the_permissions_of_the_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new("permissions");
+        let config = dir.config();
+        let mode = |path: &str| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        write(&config, "new").unwrap();
+        assert_eq!(mode(&config), 0o600);
+
+        std::fs::set_permissions(&config, std::fs::Permissions::from_mode(0o640)).unwrap();
+        write(&config, "changed").unwrap();
+        assert_eq!(mode(&config), 0o640);
+    }
+}
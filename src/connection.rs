@@ -0,0 +1,184 @@
+use crate::jellyfin;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What the server told us when testing the connection.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub server_name: String,
+    pub version: String,
+    pub latency: Duration,
+    pub key: KeyStatus,
+    /// Tracked usernames the server doesn't know, `None` when the key isn't allowed to list users
+    pub missing_users: Option<Vec<String>>,
+}
+
+/// Whether the server accepted the API key.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyStatus {
+    Valid,
+    Rejected,
+    /// The server failed for some other reason, so the key couldn't be checked
+    Unknown(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum ConnectionError {
+    InvalidUrl(String),
+    Dns(String),
+    Tls(String),
+    Unreachable(String),
+    /// Something answered, but it wasn't Jellyfin
+    WrongPath,
+    Other(String),
+}
+
+impl std::fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionError::InvalidUrl(err) => write!(f, "Invalid URL: {}", err),
+            ConnectionError::Dns(err) => write!(f, "Couldn't resolve the hostname: {}", err),
+            ConnectionError::Tls(err) => write!(f, "TLS error, check the certificate: {}", err),
+            ConnectionError::Unreachable(err) => write!(f, "Server unreachable: {}", err),
+            ConnectionError::WrongPath => {
+                write!(f, "No Jellyfin server at this URL, check the path and port")
+            }
+            ConnectionError::Other(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PublicSystemInfo {
+    server_name: String,
+    version: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct User {
+    name: String,
+}
+
+/// Checks that the URL leads to a Jellyfin server and that it accepts the API key.
+pub async fn test(
+    url: String,
+    api_key: String,
+    usernames: Vec<String>,
+) -> Result<ConnectionInfo, ConnectionError> {
+    let url = url.trim_end_matches('/').to_string();
+
+    reqwest::Url::parse(&url).map_err(|err| ConnectionError::InvalidUrl(err.to_string()))?;
+
+    let client = jellyfin::Client::new(&url, Some(&api_key));
+
+    let started = Instant::now();
+
+    let response = client
+        .get("/System/Info/Public")
+        .timeout(TIMEOUT)
+        .send()
+        .await
+        .map_err(classify)?;
+
+    let latency = started.elapsed();
+
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(ConnectionError::WrongPath);
+    }
+
+    let info: PublicSystemInfo = response
+        .error_for_status()
+        .map_err(|err| ConnectionError::Other(err.to_string()))?
+        .json()
+        .await
+        .map_err(|_| ConnectionError::WrongPath)?;
+
+    let response = client
+        .get("/System/Info")
+        .timeout(TIMEOUT)
+        .send()
+        .await
+        .map_err(classify)?;
+
+    let key = match response.status() {
+        status if status.is_success() => KeyStatus::Valid,
+        reqwest::StatusCode::UNAUTHORIZED => KeyStatus::Rejected,
+        status => KeyStatus::Unknown(status.to_string()),
+    };
+
+    let missing_users = match key {
+        KeyStatus::Valid => lookup_users(&client, usernames).await?,
+        _ => None,
+    };
+
+    Ok(ConnectionInfo {
+        server_name: info.server_name,
+        version: info.version,
+        latency,
+        key,
+        missing_users,
+    })
+}
+
+async fn lookup_users(
+    client: &jellyfin::Client,
+    usernames: Vec<String>,
+) -> Result<Option<Vec<String>>, ConnectionError> {
+    let response = client
+        .get("/Users")
+        .timeout(TIMEOUT)
+        .send()
+        .await
+        .map_err(classify)?;
+
+    // Keys of regular users can't list everyone, which is fine
+    if !response.status().is_success() {
+        return Ok(None);
+    }
+
+    let users: Vec<User> = response
+        .json()
+        .await
+        .map_err(|err| ConnectionError::Other(err.to_string()))?;
+
+    Ok(Some(
+        usernames
+            .into_iter()
+            .filter(|username| !users.iter().any(|user| &user.name == username))
+            .collect(),
+    ))
+}
+
+/// reqwest only tells us it couldn't connect, the reason is somewhere down the source chain.
+fn classify(err: reqwest::Error) -> ConnectionError {
+    let mut chain = err.to_string();
+    let mut source = std::error::Error::source(&err);
+    while let Some(err) = source {
+        chain.push_str(&format!(": {}", err));
+        source = std::error::Error::source(err);
+    }
+
+    let lowercase = chain.to_lowercase();
+
+    if err.is_timeout() {
+        ConnectionError::Unreachable("Timed out".to_string())
+    } else if ["dns", "lookup", "resolve", "name or service not known"]
+        .iter()
+        .any(|needle| lowercase.contains(needle))
+    {
+        ConnectionError::Dns(chain)
+    } else if ["certificate", "tls", "ssl", "handshake"]
+        .iter()
+        .any(|needle| lowercase.contains(needle))
+    {
+        ConnectionError::Tls(chain)
+    } else if err.is_connect() {
+        ConnectionError::Unreachable(chain)
+    } else {
+        ConnectionError::Other(chain)
+    }
+}
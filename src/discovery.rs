@@ -0,0 +1,94 @@
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+const DISCOVERY_MESSAGE: &[u8] = b"who is JellyfinServer?";
+const DISCOVERY_PORT: u16 = 7359;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Server {
+    pub name: String,
+    pub address: String,
+    pub id: String,
+}
+
+/// Broadcasts the Jellyfin discovery probe on the local network and collects
+/// every server that answers within a couple of seconds.
+pub async fn discover() -> Result<Vec<Server>, String> {
+    probe(
+        SocketAddr::from(([255, 255, 255, 255], DISCOVERY_PORT)),
+        Duration::from_secs(2),
+    )
+    .await
+}
+
+pub async fn probe(target: SocketAddr, timeout: Duration) -> Result<Vec<Server>, String> {
+    let socket = UdpSocket::bind("0.0.0.0:0")
+        .await
+        .map_err(|err| err.to_string())?;
+    socket.set_broadcast(true).map_err(|err| err.to_string())?;
+    socket
+        .send_to(DISCOVERY_MESSAGE, target)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let mut servers: Vec<Server> = Vec::new();
+    let mut buf = [0u8; 2048];
+
+    loop {
+        let len = match tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+            Ok(Ok((len, _))) => len,
+            Ok(Err(err)) => return Err(err.to_string()),
+            Err(_) => break,
+        };
+
+        // Anything that isn't a Jellyfin reply is just ignored
+        if let Ok(server) = serde_json::from_slice::<Server>(&buf[..len]) {
+            if !servers.iter().any(|known| known.id == server.id) {
+                servers.push(server);
+            }
+        }
+    }
+
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REPLY: &[u8] =
+        br#"{"Address":"http://192.168.1.10:8096","Id":"abc123","Name":"Living room","EndpointAddress":null}"#;
+
+    #[tokio::test]
+    async fn probe_parses_replies_and_skips_garbage() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let target = server.local_addr().unwrap();
+
+        let responder = tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let (len, from) = server.recv_from(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], DISCOVERY_MESSAGE);
+
+            server.send_to(b"not json at all", from).await.unwrap();
+            server.send_to(REPLY, from).await.unwrap();
+            // Servers with more than one interface answer more than once
+            server.send_to(REPLY, from).await.unwrap();
+        });
+
+        let servers = probe(target, Duration::from_millis(500)).await.unwrap();
+        responder.await.unwrap();
+
+        assert_eq!(
+            servers,
+            vec![Server {
+                name: "Living room".to_string(),
+                address: "http://192.168.1.10:8096".to_string(),
+                id: "abc123".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use chrono::{DateTime, Local};
+use jellyfin_rpc::prelude::Event;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How many events are kept before the oldest ones get dropped.
+const CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Error,
+}
+
+impl Level {
+    pub const ALL: [Level; 3] = [Level::Debug, Level::Info, Level::Error];
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Level::Debug => write!(f, "DEBUG"),
+            Level::Info => write!(f, "INFO"),
+            Level::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    JsonLines,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub timestamp: DateTime<Local>,
+    pub level: Level,
+    pub title: String,
+    pub message: String,
+}
+
+impl std::fmt::Display for Entry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} [{}] {}",
+            self.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            self.level,
+            self.title
+        )?;
+
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Event> for Entry {
+    fn from(event: Event) -> Self {
+        let (level, title, message) = match event {
+            Event::Information(title, message) => (Level::Info, title, message),
+            Event::Error(title, message) => (Level::Error, title, message),
+            event => (Level::Debug, format!("{:?}", event), String::new()),
+        };
+
+        Self {
+            timestamp: Local::now(),
+            level,
+            title,
+            message,
+        }
+    }
+}
+
+impl Entry {
+    fn matches(&self, level: Level, filter: &str) -> bool {
+        let filter = filter.to_lowercase();
+
+        self.level >= level
+            && (filter.is_empty()
+                || self.title.to_lowercase().contains(&filter)
+                || self.message.to_lowercase().contains(&filter))
+    }
+}
+
+#[derive(Default)]
+pub struct Log {
+    entries: VecDeque<Entry>,
+}
+
+impl Log {
+    pub fn push(&mut self, entry: Entry) {
+        if self.entries.len() == CAPACITY {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(entry);
+    }
+
+    /// Newest entries first, only the ones at or above `level` that contain `filter`.
+    pub fn filtered<'a>(
+        &'a self,
+        level: Level,
+        filter: &'a str,
+    ) -> impl Iterator<Item = &'a Entry> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(move |entry| entry.matches(level, filter))
+    }
+
+    /// Writes the whole log to a timestamped file in `dir` and returns its path.
+    pub fn export(&self, dir: &Path, format: Format) -> std::io::Result<PathBuf> {
+        let extension = match format {
+            Format::Text => "log",
+            Format::JsonLines => "jsonl",
+        };
+
+        let path = dir.join(format!(
+            "jellyfin-rpc-iced-{}.{}",
+            Local::now().format("%Y%m%d-%H%M%S"),
+            extension
+        ));
+
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+
+        for entry in &self.entries {
+            match format {
+                Format::Text => writeln!(file, "{}", entry)?,
+                Format::JsonLines => writeln!(
+                    file,
+                    "{}",
+                    json!({
+                        "timestamp": entry.timestamp.to_rfc3339(),
+                        "level": entry.level.to_string(),
+                        "title": entry.title,
+                        "message": entry.message,
+                    })
+                )?,
+            }
+        }
+
+        file.flush()?;
+
+        Ok(path)
+    }
+}
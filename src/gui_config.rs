@@ -0,0 +1,434 @@
+use iced::theme::Palette;
+use iced::{Color, Theme};
+use jellyfin_rpc::prelude::config::Config;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Settings that only matter to the GUI, stored under the `gui` key of the
+/// regular config file so jellyfin-rpc can keep reading the rest of it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GuiConfig {
+    #[serde(default)]
+    pub theme: ThemeChoice,
+    #[serde(default)]
+    pub palette: CustomPalette,
+    /// Every tracked user in order of priority, including the parked ones
+    #[serde(default)]
+    pub users: Vec<TrackedUser>,
+    #[serde(default)]
+    pub sessions: SessionFilter,
+    /// jellyfin-rpc only knows library names, so we keep the IDs to follow renames
+    #[serde(default)]
+    pub blacklisted_libraries: Vec<BlacklistedLibrary>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlacklistedLibrary {
+    /// Empty until the library has been found on the server
+    pub id: String,
+    pub name: String,
+}
+
+/// Parked users are remembered here but left out of `jellyfin.username`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackedUser {
+    pub name: String,
+    pub enabled: bool,
+}
+
+/// Picks sessions by the name of the client or the device they're running on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMatch {
+    Client(String),
+    Device(String),
+}
+
+impl std::fmt::Display for SessionMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SessionMatch::Client(client) => write!(f, "Client: {}", client),
+            SessionMatch::Device(device) => write!(f, "Device: {}", device),
+        }
+    }
+}
+
+impl SessionMatch {
+    pub fn matches(&self, client: &str, device: &str) -> bool {
+        match self {
+            SessionMatch::Client(name) => name == client,
+            SessionMatch::Device(name) => name == device,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionFilter {
+    #[serde(default)]
+    pub pinned: Vec<SessionMatch>,
+    #[serde(default)]
+    pub excluded: Vec<SessionMatch>,
+}
+
+impl SessionFilter {
+    /// Excluded sessions never count, and once anything is pinned only the pinned ones do.
+    pub fn allows(&self, client: &str, device: &str) -> bool {
+        if self
+            .excluded
+            .iter()
+            .any(|rule| rule.matches(client, device))
+        {
+            return false;
+        }
+
+        self.pinned.is_empty() || self.pinned.iter().any(|rule| rule.matches(client, device))
+    }
+
+    pub fn pin(&mut self, rule: SessionMatch, val: bool) {
+        self.remove(&rule);
+        if val {
+            self.pinned.push(rule);
+        }
+    }
+
+    pub fn exclude(&mut self, rule: SessionMatch, val: bool) {
+        self.remove(&rule);
+        if val {
+            self.excluded.push(rule);
+        }
+    }
+
+    pub fn remove(&mut self, rule: &SessionMatch) {
+        self.pinned.retain(|pinned| pinned != rule);
+        self.excluded.retain(|excluded| excluded != rule);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeChoice {
+    Light,
+    #[default]
+    Dark,
+    System,
+    Custom,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaletteColor {
+    Background,
+    Text,
+    Primary,
+    Success,
+    Danger,
+}
+
+/// Colors are kept as hex strings so half-typed values survive in the text inputs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CustomPalette {
+    pub background: String,
+    pub text: String,
+    pub primary: String,
+    pub success: String,
+    pub danger: String,
+}
+
+impl Default for CustomPalette {
+    fn default() -> Self {
+        Self {
+            background: to_hex(Palette::DARK.background),
+            text: to_hex(Palette::DARK.text),
+            primary: to_hex(Palette::DARK.primary),
+            success: to_hex(Palette::DARK.success),
+            danger: to_hex(Palette::DARK.danger),
+        }
+    }
+}
+
+impl CustomPalette {
+    pub fn get(&self, color: PaletteColor) -> &String {
+        match color {
+            PaletteColor::Background => &self.background,
+            PaletteColor::Text => &self.text,
+            PaletteColor::Primary => &self.primary,
+            PaletteColor::Success => &self.success,
+            PaletteColor::Danger => &self.danger,
+        }
+    }
+
+    pub fn set(&mut self, color: PaletteColor, value: String) {
+        match color {
+            PaletteColor::Background => self.background = value,
+            PaletteColor::Text => self.text = value,
+            PaletteColor::Primary => self.primary = value,
+            PaletteColor::Success => self.success = value,
+            PaletteColor::Danger => self.danger = value,
+        }
+    }
+
+    /// Invalid colors fall back to the matching color of the dark theme.
+    pub fn palette(&self) -> Palette {
+        Palette {
+            background: parse_hex(&self.background).unwrap_or(Palette::DARK.background),
+            text: parse_hex(&self.text).unwrap_or(Palette::DARK.text),
+            primary: parse_hex(&self.primary).unwrap_or(Palette::DARK.primary),
+            success: parse_hex(&self.success).unwrap_or(Palette::DARK.success),
+            danger: parse_hex(&self.danger).unwrap_or(Palette::DARK.danger),
+        }
+    }
+}
+
+impl GuiConfig {
+    pub fn load(config_path: &str) -> Self {
+        std::fs::read_to_string(config_path)
+            .ok()
+            .and_then(|contents| serde_json::from_str::<Value>(&contents).ok())
+            .and_then(|value| value.get("gui").cloned())
+            .and_then(|gui| serde_json::from_value(gui).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn theme(&self) -> Theme {
+        match self.theme {
+            ThemeChoice::Light => Theme::Light,
+            ThemeChoice::Dark => Theme::Dark,
+            ThemeChoice::System => match dark_light::detect() {
+                dark_light::Mode::Light => Theme::Light,
+                _ => Theme::Dark,
+            },
+            ThemeChoice::Custom => Theme::custom(self.palette.palette()),
+        }
+    }
+}
+
+/// Brings the user list in line with `jellyfin.username`, which might have been
+/// edited by hand. The usernames from the config keep their order and take the
+/// places of the enabled users, parked users stay where they were.
+pub fn reconcile_users(users: &[TrackedUser], usernames: &[String]) -> Vec<TrackedUser> {
+    let mut usernames = usernames.iter();
+    let mut reconciled: Vec<TrackedUser> = Vec::new();
+
+    for user in users {
+        if !user.enabled {
+            reconciled.push(user.clone());
+        } else if let Some(name) = usernames.next() {
+            reconciled.push(TrackedUser {
+                name: name.to_string(),
+                enabled: true,
+            });
+        }
+    }
+
+    reconciled.extend(usernames.map(|name| TrackedUser {
+        name: name.to_string(),
+        enabled: true,
+    }));
+
+    // A parked user that shows up in the config again was enabled by hand
+    let enabled: Vec<String> = reconciled
+        .iter()
+        .filter(|user| user.enabled)
+        .map(|user| user.name.clone())
+        .collect();
+    reconciled.retain(|user| user.enabled || !enabled.contains(&user.name));
+
+    reconciled
+}
+
+/// Matches the library names of `blacklist.libraries` up with the IDs we know,
+/// `libraries` are the `(id, name)` pairs of the server. Names we've never seen
+/// an ID for get one by name, known IDs pick up the current name of the library.
+pub fn reconcile_libraries(
+    blacklisted: &[BlacklistedLibrary],
+    names: &[String],
+    libraries: &[(String, String)],
+) -> Vec<BlacklistedLibrary> {
+    let mut reconciled: Vec<BlacklistedLibrary> = Vec::new();
+
+    for name in names {
+        let mut library = blacklisted
+            .iter()
+            .find(|library| &library.name == name)
+            .cloned()
+            .unwrap_or(BlacklistedLibrary {
+                id: String::new(),
+                name: name.to_string(),
+            });
+
+        if library.id.is_empty() {
+            if let Some((id, _)) = libraries.iter().find(|(_, current)| current == name) {
+                library.id = id.to_string();
+            }
+        } else if let Some((_, current)) = libraries.iter().find(|(id, _)| id == &library.id) {
+            library.name = current.to_string();
+        }
+
+        if !reconciled.contains(&library) {
+            reconciled.push(library);
+        }
+    }
+
+    reconciled
+}
+
+/// Serializes the jellyfin-rpc config with the GUI section added on top.
+pub fn merge(config: &Config, gui: &GuiConfig) -> serde_json::Result<String> {
+    let mut value = serde_json::to_value(config)?;
+
+    if let Value::Object(map) = &mut value {
+        map.insert("gui".to_string(), serde_json::to_value(gui)?);
+    }
+
+    serde_json::to_string_pretty(&value)
+}
+
+fn parse_hex(hex: &str) -> Option<Color> {
+    let hex = hex.trim().trim_start_matches('#');
+
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let color = u32::from_str_radix(hex, 16).ok()?;
+
+    Some(Color::from_rgb8(
+        (color >> 16) as u8,
+        (color >> 8) as u8,
+        color as u8,
+    ))
+}
+
+fn to_hex(color: Color) -> String {
+    let [r, g, b, _] = color.into_rgba8();
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(name: &str, enabled: bool) -> TrackedUser {
+        TrackedUser {
+            name: name.to_string(),
+            enabled,
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn reconcile_users_follows_hand_reordered_config() {
+        let users = [
+            user("alice", true),
+            user("parked", false),
+            user("bob", true),
+        ];
+
+        assert_eq!(
+            reconcile_users(&users, &names(&["bob", "alice"])),
+            vec![
+                user("bob", true),
+                user("parked", false),
+                user("alice", true)
+            ]
+        );
+    }
+
+    #[test]
+    fn reconcile_users_enables_parked_user_added_to_config() {
+        let users = [user("alice", true), user("parked", false)];
+
+        assert_eq!(
+            reconcile_users(&users, &names(&["alice", "parked"])),
+            vec![user("alice", true), user("parked", true)]
+        );
+    }
+
+    #[test]
+    fn reconcile_users_drops_users_removed_from_config() {
+        let users = [
+            user("alice", true),
+            user("bob", true),
+            user("parked", false),
+        ];
+
+        assert_eq!(
+            reconcile_users(&users, &names(&["alice"])),
+            vec![user("alice", true), user("parked", false)]
+        );
+    }
+
+    fn library(id: &str, name: &str) -> BlacklistedLibrary {
+        BlacklistedLibrary {
+            id: id.to_string(),
+            name: name.to_string(),
+        }
+    }
+
+    fn server(libraries: &[(&str, &str)]) -> Vec<(String, String)> {
+        libraries
+            .iter()
+            .map(|(id, name)| (id.to_string(), name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reconcile_libraries_follows_rename_on_server() {
+        let blacklisted = [library("1", "Movies")];
+
+        assert_eq!(
+            reconcile_libraries(
+                &blacklisted,
+                &names(&["Movies"]),
+                &server(&[("1", "Films"), ("2", "Shows")])
+            ),
+            vec![library("1", "Films")]
+        );
+    }
+
+    #[test]
+    fn reconcile_libraries_matches_new_name_by_name() {
+        assert_eq!(
+            reconcile_libraries(&[], &names(&["Shows"]), &server(&[("2", "Shows")])),
+            vec![library("2", "Shows")]
+        );
+    }
+
+    #[test]
+    fn reconcile_libraries_keeps_library_never_seen() {
+        assert_eq!(
+            reconcile_libraries(&[], &names(&["Old stuff"]), &server(&[("1", "Movies")])),
+            vec![library("", "Old stuff")]
+        );
+    }
+
+    #[test]
+    fn session_filter_allows_everything_by_default() {
+        assert!(SessionFilter::default().allows("Jellyfin Web", "Desktop"));
+    }
+
+    #[test]
+    fn session_filter_only_allows_pinned_sessions_once_any_are_pinned() {
+        let filter = SessionFilter {
+            pinned: vec![SessionMatch::Client("Jellyfin Media Player".to_string())],
+            excluded: Vec::new(),
+        };
+
+        assert!(filter.allows("Jellyfin Media Player", "Desktop"));
+        assert!(!filter.allows("Jellyfin Web", "Desktop"));
+    }
+
+    #[test]
+    fn session_filter_excluded_wins_over_pinned() {
+        let filter = SessionFilter {
+            pinned: vec![SessionMatch::Client("Jellyfin Android TV".to_string())],
+            excluded: vec![SessionMatch::Device("Kids TV".to_string())],
+        };
+
+        assert!(filter.allows("Jellyfin Android TV", "Living room"));
+        assert!(!filter.allows("Jellyfin Android TV", "Kids TV"));
+        assert!(!filter.allows("Jellyfin Web", "Living room"));
+    }
+}
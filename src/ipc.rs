@@ -0,0 +1,347 @@
+use crate::event_log::{Entry, Level};
+use crate::server;
+use clap::Subcommand;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+/// Commands accepted over the control socket, also used as `ctl` subcommands.
+#[derive(Debug, Clone, PartialEq, Subcommand)]
+pub enum Request {
+    /// Start the presence loop
+    Start,
+    /// Stop the presence loop
+    Stop,
+    /// Reload the config file
+    #[command(name = "reload")]
+    ReloadConfig,
+    /// Restart the presence service
+    Restart,
+    /// Print the current status as JSON
+    Status,
+    /// Bring the window of the running instance to the front
+    Show {
+        /// Switch the running instance to this config file
+        #[arg(long = "config")]
+        config: Option<String>,
+    },
+}
+
+impl Request {
+    fn to_line(&self) -> String {
+        match self {
+            Request::Start => "start".to_string(),
+            Request::Stop => "stop".to_string(),
+            Request::ReloadConfig => "reload".to_string(),
+            Request::Restart => "restart".to_string(),
+            Request::Status => "status".to_string(),
+            Request::Show { config: None } => "show".to_string(),
+            Request::Show {
+                config: Some(config),
+            } => format!("show {}", config),
+        }
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let (command, argument) = match line.trim().split_once(' ') {
+            Some((command, argument)) => (command, Some(argument.to_string())),
+            None => (line.trim(), None),
+        };
+
+        match command {
+            "start" => Some(Request::Start),
+            "stop" => Some(Request::Stop),
+            "reload" => Some(Request::ReloadConfig),
+            "restart" => Some(Request::Restart),
+            "status" => Some(Request::Status),
+            "show" => Some(Request::Show { config: argument }),
+            _ => None,
+        }
+    }
+}
+
+/// What gets reported back for [`Request::Status`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Status {
+    pub status: String,
+    pub error: String,
+    pub discord_connected: bool,
+}
+
+impl Status {
+    /// Keeps up with the events of the presence loop, for the GUI and headless mode alike.
+    pub fn update(&mut self, entry: &Entry) {
+        if entry.title == server::DISCORD_CONNECTED {
+            self.discord_connected = true;
+        } else if entry.title == server::DISCORD_DISCONNECTED {
+            self.discord_connected = false;
+            self.error = entry.message.clone();
+        } else {
+            match entry.level {
+                Level::Info => self.status = entry.title.clone(),
+                Level::Error => self.error = entry.message.clone(),
+                Level::Debug => (),
+            }
+        }
+    }
+}
+
+/// Each user gets their own instance, so the socket or pipe is named after them.
+fn instance_name() -> String {
+    match std::env::var("USER").or_else(|_| std::env::var("USERNAME")) {
+        Ok(user) => format!("jellyfin-rpc-iced-{}", user),
+        Err(_) => "jellyfin-rpc-iced".to_string(),
+    }
+}
+
+/// The control socket and the instance lock go into the user's runtime dir.
+/// Without one they fall back to a private directory in the shared temp dir, so
+/// other users can't swap in a socket of their own.
+#[cfg(unix)]
+fn runtime_dir() -> std::io::Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(PathBuf::from(dir));
+    }
+
+    let dir = std::env::temp_dir().join(instance_name());
+    private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// The temp dir already belongs to the user on Windows.
+#[cfg(not(unix))]
+fn runtime_dir() -> std::io::Result<PathBuf> {
+    Ok(std::env::temp_dir())
+}
+
+#[cfg(unix)]
+fn socket_path() -> std::io::Result<PathBuf> {
+    Ok(runtime_dir()?.join(format!("{}.sock", instance_name())))
+}
+
+/// Held by the running instance until it exits. Unlike checking for the socket,
+/// only one of several launches at the same time can get it.
+pub struct Lock {
+    _file: std::fs::File,
+}
+
+/// The control socket, bound before anything else starts so the instance
+/// that got the [`Lock`] is also the one answering.
+pub struct Listener {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixListener,
+}
+
+/// Makes this the running instance, failing with `AddrInUse` when there already is one.
+pub fn acquire() -> std::io::Result<(Lock, Listener)> {
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(runtime_dir()?.join(format!("{}.lock", instance_name())))?;
+
+    file.try_lock().map_err(|err| match err {
+        std::fs::TryLockError::WouldBlock => std::io::Error::new(
+            std::io::ErrorKind::AddrInUse,
+            "Jellyfin-RPC-Iced is already running",
+        ),
+        std::fs::TryLockError::Error(err) => err,
+    })?;
+
+    Ok((
+        Lock { _file: file },
+        Listener {
+            #[cfg(unix)]
+            socket: bind()?,
+        },
+    ))
+}
+
+#[cfg(unix)]
+fn bind() -> std::io::Result<std::os::unix::net::UnixListener> {
+    let path = socket_path()?;
+
+    // With the lock held, a socket that's still around was left behind by a crash
+    match std::fs::remove_file(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => (),
+    }
+
+    let socket = std::os::unix::net::UnixListener::bind(&path)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket)
+}
+
+/// Creates `dir` only we can get into, or makes sure that's what the existing one is.
+#[cfg(unix)]
+fn private_dir(dir: &std::path::Path) -> std::io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(err) if err.kind() != std::io::ErrorKind::AlreadyExists => return Err(err),
+        _ => (),
+    }
+
+    let metadata = std::fs::symlink_metadata(dir)?;
+
+    // Anything we create is ours, which is the easiest way to learn our own uid
+    let probe = dir.join(format!(".probe-{}", std::process::id()));
+    let owner = std::fs::File::create(&probe).and_then(|file| file.metadata());
+    std::fs::remove_file(&probe).ok();
+
+    if !metadata.is_dir()
+        || metadata.permissions().mode() & 0o077 != 0
+        || owner?.uid() != metadata.uid()
+    {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!("{} isn't a private directory of this user", dir.display()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// On Windows the named pipe takes the place of the socket. Pipes are local to
+/// the machine and only the first instance gets to create one.
+#[cfg(windows)]
+fn pipe_name() -> String {
+    format!(r"\\.\pipe\{}", instance_name())
+}
+
+#[cfg(unix)]
+async fn connect() -> std::io::Result<tokio::net::UnixStream> {
+    tokio::net::UnixStream::connect(socket_path()?).await
+}
+
+#[cfg(windows)]
+async fn connect() -> std::io::Result<tokio::net::windows::named_pipe::NamedPipeClient> {
+    tokio::net::windows::named_pipe::ClientOptions::new().open(pipe_name())
+}
+
+/// Sends a single request to the running instance and returns its reply.
+#[cfg(any(unix, windows))]
+pub async fn send(request: Request) -> Result<String, String> {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let stream = connect()
+        .await
+        .map_err(|_| "Jellyfin-RPC-Iced is not running".to_string())?;
+    let (read, mut write) = tokio::io::split(stream);
+
+    write
+        .write_all(format!("{}\n", request.to_line()).as_bytes())
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let mut reply = String::new();
+    BufReader::new(read)
+        .read_line(&mut reply)
+        .await
+        .map_err(|err| err.to_string())?;
+
+    match reply.trim().strip_prefix("error: ") {
+        Some(err) => Err(err.to_string()),
+        None => Ok(reply.trim().to_string()),
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub async fn send(_request: Request) -> Result<String, String> {
+    Err("The control socket isn't supported on this platform".to_string())
+}
+
+/// Listens on the control socket until it fails. Status requests are answered
+/// straight from `status`, everything else is passed to `handler`.
+#[cfg(unix)]
+pub async fn listen<F>(
+    listener: Listener,
+    status: Arc<Mutex<Status>>,
+    handler: F,
+) -> std::io::Result<()>
+where
+    F: Fn(Request) -> Result<(), String> + Send + Sync + 'static,
+{
+    let listener = tokio::net::UnixListener::from_std(listener.socket)?;
+    let handler = Arc::new(handler);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(serve(stream, status.clone(), handler.clone()));
+    }
+}
+
+#[cfg(windows)]
+pub async fn listen<F>(
+    _listener: Listener,
+    status: Arc<Mutex<Status>>,
+    handler: F,
+) -> std::io::Result<()>
+where
+    F: Fn(Request) -> Result<(), String> + Send + Sync + 'static,
+{
+    use tokio::net::windows::named_pipe::ServerOptions;
+
+    // Pipes need the runtime that serves them, so unlike the socket this one is
+    // created here. Holding the lock, it only fails if someone else took the name.
+    let mut server = ServerOptions::new()
+        .first_pipe_instance(true)
+        .create(pipe_name())?;
+    let handler = Arc::new(handler);
+
+    loop {
+        server.connect().await?;
+
+        // Every client gets an instance of the pipe to itself, so the next one needs a new one
+        let stream = std::mem::replace(&mut server, ServerOptions::new().create(pipe_name())?);
+        tokio::spawn(serve(stream, status.clone(), handler.clone()));
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub async fn listen<F>(
+    _listener: Listener,
+    _status: Arc<Mutex<Status>>,
+    _handler: F,
+) -> std::io::Result<()>
+where
+    F: Fn(Request) -> Result<(), String> + Send + Sync + 'static,
+{
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "The control socket isn't supported on this platform",
+    ))
+}
+
+/// Answers the one request a client sends per connection.
+#[cfg(any(unix, windows))]
+async fn serve<S, F>(stream: S, status: Arc<Mutex<Status>>, handler: Arc<F>)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite,
+    F: Fn(Request) -> Result<(), String>,
+{
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let (read, mut write) = tokio::io::split(stream);
+    let mut line = String::new();
+
+    if BufReader::new(read).read_line(&mut line).await.is_err() {
+        return;
+    }
+
+    let reply = match Request::parse(&line) {
+        Some(Request::Status) => {
+            serde_json::to_string(&*status.lock().unwrap()).unwrap_or_default()
+        }
+        Some(request) => match handler(request) {
+            Ok(()) => "ok".to_string(),
+            Err(err) => format!("error: {}", err),
+        },
+        None => format!("error: Unknown command {:?}", line.trim()),
+    };
+
+    write
+        .write_all(format!("{}\n", reply).as_bytes())
+        .await
+        .ok();
+}
@@ -0,0 +1,139 @@
+use crate::jellyfin;
+use crate::users;
+use serde::Deserialize;
+
+/// A library as seen by one of the tracked users.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerLibrary {
+    pub id: String,
+    pub name: String,
+    pub collection_type: Option<String>,
+    pub item_count: Option<u64>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Views {
+    items: Vec<View>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct View {
+    id: String,
+    name: String,
+    collection_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ItemCount {
+    total_record_count: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct User {
+    id: String,
+}
+
+/// Collects the libraries every tracked user can see. Works with the keys of
+/// regular users too, they just only get to see their own libraries.
+pub async fn get_libraries(
+    url: String,
+    api_key: String,
+    usernames: Vec<String>,
+) -> Result<Vec<ServerLibrary>, String> {
+    let client = jellyfin::Client::new(&url, Some(&api_key));
+
+    let user_ids: Vec<String> = match users::get_users(url.clone(), api_key.clone()).await {
+        Ok(users) => users
+            .into_iter()
+            .filter(|user| usernames.contains(&user.name))
+            .map(|user| user.id)
+            .collect(),
+        // Not an admin key, but it still belongs to a user
+        Err(_) => vec![get_own_id(&client).await?],
+    };
+
+    // Otherwise it would look like the server has no libraries at all
+    if user_ids.is_empty() {
+        return Err(match usernames.is_empty() {
+            true => "No users are tracked yet".to_string(),
+            false => format!(
+                "None of the tracked users ({}) exist on this server",
+                usernames.join(", ")
+            ),
+        });
+    }
+
+    let mut libraries: Vec<ServerLibrary> = Vec::new();
+
+    for user_id in user_ids {
+        let views: Views = client
+            .get(&format!("/Users/{}/Views", user_id))
+            .send()
+            .await
+            .map_err(|err| err.to_string())?
+            .error_for_status()
+            .map_err(|err| err.to_string())?
+            .json()
+            .await
+            .map_err(|err| format!("Unexpected response from the server: {}", err))?;
+
+        for view in views.items {
+            if libraries.iter().any(|library| library.id == view.id) {
+                continue;
+            }
+
+            let item_count = get_item_count(&client, &user_id, &view.id).await;
+
+            libraries.push(ServerLibrary {
+                id: view.id,
+                name: view.name,
+                collection_type: view.collection_type,
+                item_count,
+            });
+        }
+    }
+
+    Ok(libraries)
+}
+
+async fn get_own_id(client: &jellyfin::Client) -> Result<String, String> {
+    let user: User = client
+        .get("/Users/Me")
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|_| "This API key can't list users or libraries".to_string())?
+        .json()
+        .await
+        .map_err(|err| format!("Unexpected response from the server: {}", err))?;
+
+    Ok(user.id)
+}
+
+/// The count is only nice to have, so failing to get it isn't an error.
+async fn get_item_count(client: &jellyfin::Client, user_id: &str, library_id: &str) -> Option<u64> {
+    let count: ItemCount = client
+        .get("/Items")
+        .query(&[
+            ("userId", user_id),
+            ("parentId", library_id),
+            ("recursive", "true"),
+            ("isFolder", "false"),
+            ("limit", "0"),
+        ])
+        .send()
+        .await
+        .ok()?
+        .error_for_status()
+        .ok()?
+        .json()
+        .await
+        .ok()?;
+
+    Some(count.total_record_count)
+}
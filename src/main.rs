@@ -1,9 +1,23 @@
 #![windows_subsystem = "windows"]
 
 use iced::{Application, Settings};
+use jellyfin_rpc::prelude::config::get_config_path;
+mod config_file;
+mod connection;
+mod discovery;
+mod event_log;
+mod gui_config;
+mod ipc;
+mod jellyfin;
+mod libraries;
+mod now_playing;
 mod server;
+mod setup;
+mod tray;
+mod users;
+mod watcher;
 mod window;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use window::Gui;
 
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
@@ -21,16 +35,136 @@ pub struct Args {
         help = "Path to image urls file for imgur"
     )]
     pub image_urls: Option<String>,
+    #[arg(
+        long = "headless",
+        help = "Run without a window and log to stdout, using the same config"
+    )]
+    pub headless: bool,
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+pub enum Commands {
+    /// Control the running instance over its local socket
+    Ctl {
+        #[command(subcommand)]
+        request: ipc::Request,
+    },
+}
+
+/// Resolves the config file path and makes sure its directory exists.
+pub fn config_path(args: &Args) -> String {
+    let config_path = match args.config.clone() {
+        Some(path) => path,
+        None => get_config_path().unwrap_or_else(|err| {
+            eprintln!("Error determining config path: {:?}", err);
+            std::process::exit(1)
+        }),
+    };
+
+    std::fs::create_dir_all(
+        std::path::Path::new(&config_path)
+            .parent()
+            .expect("Invalid config file path"),
+    )
+    .ok();
+
+    canonical_path(&config_path)
+}
+
+/// Makes paths to the same config file comparable, even before the file exists.
+pub fn canonical_path(path: &str) -> String {
+    let path = std::path::Path::new(path);
+
+    let canonical = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => std::fs::canonicalize(match dir.as_os_str().is_empty() {
+            true => std::path::Path::new("."),
+            false => dir,
+        })
+        .map(|dir| dir.join(name)),
+        _ => std::fs::canonicalize(path),
+    };
+
+    canonical
+        .map(|path| path.to_string_lossy().to_string())
+        .unwrap_or(path.to_string_lossy().to_string())
 }
 
+/// Being a GUI app on Windows means there's no console to print to, so `ctl`,
+/// `--headless` and `--help` borrow the one they were started from.
+#[cfg(windows)]
+fn attach_console() {
+    const ATTACH_PARENT_PROCESS: u32 = u32::MAX;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn AttachConsole(process_id: u32) -> i32;
+    }
+
+    // Fails when started from Explorer, there's nothing to print to then anyway
+    unsafe {
+        AttachConsole(ATTACH_PARENT_PROCESS);
+    }
+}
+
+#[cfg(not(windows))]
+fn attach_console() {}
+
 #[tokio::main()]
 pub async fn main() -> iced::Result {
+    attach_console();
+
+    let args = Args::parse();
+
+    if let Some(Commands::Ctl { request }) = args.command {
+        // The running instance resolves paths against its own working directory
+        let request = match request {
+            ipc::Request::Show { config } => ipc::Request::Show {
+                config: config.as_deref().map(canonical_path),
+            },
+            request => request,
+        };
+
+        match ipc::send(request).await {
+            Ok(reply) => {
+                println!("{}", reply);
+                return Ok(());
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                std::process::exit(1)
+            }
+        }
+    }
+
+    // Only one instance should be talking to Discord at a time
+    let (_lock, listener) = match ipc::acquire() {
+        Ok(acquired) => acquired,
+        Err(err) if err.kind() == std::io::ErrorKind::AddrInUse && !args.headless => {
+            let config = args.config.as_deref().map(canonical_path);
+
+            ipc::send(ipc::Request::Show { config }).await.ok();
+            return Ok(());
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1)
+        }
+    };
+
+    if args.headless {
+        server::run_headless(config_path(&args), args.image_urls, listener).await;
+        return Ok(());
+    }
+
     Gui::run(Settings {
         window: iced::window::Settings {
             size: (350, 500),
             resizable: false,
             ..Default::default()
         },
-        ..Default::default()
+        exit_on_close_request: false,
+        ..Settings::with_flags((args, listener))
     })
 }
@@ -0,0 +1,442 @@
+use crate::gui_config::SessionFilter;
+use crate::jellyfin;
+use serde::Deserialize;
+
+const TICKS_PER_SECOND: u64 = 10_000_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NowPlaying {
+    pub item_id: String,
+    pub title: String,
+    pub subtitle: String,
+    pub media_type: String,
+    pub position: u64,
+    pub runtime: Option<u64>,
+    pub paused: bool,
+}
+
+impl NowPlaying {
+    pub fn icon(&self) -> &'static str {
+        match self.media_type.as_str() {
+            "Movie" => "🎬",
+            "Episode" | "TvChannel" => "📺",
+            "Audio" => "🎵",
+            "AudioBook" => "🎧",
+            "Book" => "📖",
+            _ => "▶",
+        }
+    }
+}
+
+/// Whose session Discord should be showing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Choice {
+    /// None of the tracked users are playing anything
+    Nothing,
+    /// Only sessions the filter doesn't allow are playing something
+    Filtered,
+    /// jellyfin-rpc can be left to find the session of this user
+    User(String),
+    /// The user is playing on sessions the filter doesn't allow as well, and
+    /// jellyfin-rpc can't tell them apart, so this one is followed directly
+    Session {
+        user: String,
+        id: String,
+        device: String,
+    },
+}
+
+impl Choice {
+    pub fn user(&self) -> Option<&str> {
+        match self {
+            Choice::User(user) | Choice::Session { user, .. } => Some(user),
+            Choice::Nothing | Choice::Filtered => None,
+        }
+    }
+}
+
+/// A client someone is signed in to, as shown in the sessions panel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    pub user_name: String,
+    pub client: String,
+    pub device_name: String,
+    pub now_playing: Option<String>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Session {
+    #[serde(default)]
+    id: String,
+    user_name: Option<String>,
+    #[serde(default)]
+    client: String,
+    #[serde(default)]
+    device_name: String,
+    now_playing_item: Option<Item>,
+    play_state: Option<PlayState>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Item {
+    id: String,
+    name: String,
+    #[serde(rename = "Type")]
+    media_type: String,
+    series_name: Option<String>,
+    parent_index_number: Option<u32>,
+    index_number: Option<u32>,
+    artists: Option<Vec<String>>,
+    album: Option<String>,
+    production_year: Option<u32>,
+    run_time_ticks: Option<u64>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PlayState {
+    position_ticks: Option<u64>,
+    is_paused: Option<bool>,
+}
+
+impl From<Item> for NowPlaying {
+    fn from(item: Item) -> Self {
+        let subtitle = match item.media_type.as_str() {
+            "Episode" => format!(
+                "{} S{:02}E{:02}",
+                item.series_name.unwrap_or_default(),
+                item.parent_index_number.unwrap_or_default(),
+                item.index_number.unwrap_or_default()
+            ),
+            "Audio" => {
+                let artists = item.artists.unwrap_or_default().join(", ");
+                match item.album {
+                    Some(album) => format!("By {} - {}", artists, album),
+                    None => format!("By {}", artists),
+                }
+            }
+            _ => item
+                .production_year
+                .map(|year| year.to_string())
+                .unwrap_or_default(),
+        };
+
+        Self {
+            item_id: item.id,
+            title: item.name,
+            subtitle,
+            media_type: item.media_type,
+            position: 0,
+            runtime: item.run_time_ticks.map(|ticks| ticks / TICKS_PER_SECOND),
+            paused: false,
+        }
+    }
+}
+
+async fn get_all_sessions(url: &str, api_key: &str) -> Result<Vec<Session>, String> {
+    jellyfin::Client::new(url, Some(api_key))
+        .get("/Sessions")
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json()
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Lists the sessions of the tracked users.
+pub async fn get_sessions(
+    url: String,
+    api_key: String,
+    usernames: Vec<String>,
+) -> Result<Vec<SessionInfo>, String> {
+    let sessions = get_all_sessions(&url, &api_key).await?;
+
+    Ok(sessions
+        .into_iter()
+        .filter_map(|session| {
+            let user_name = session.user_name.filter(|name| usernames.contains(name))?;
+
+            Some(SessionInfo {
+                user_name,
+                client: session.client,
+                device_name: session.device_name,
+                now_playing: session
+                    .now_playing_item
+                    .map(|item| NowPlaying::from(item).title),
+            })
+        })
+        .collect())
+}
+
+/// Picks the session to show, when several tracked users are playing something
+/// the one listed first in `usernames` wins. Sessions the filter doesn't allow
+/// are skipped.
+fn choose<'a>(
+    sessions: &'a [Session],
+    usernames: &[String],
+    filter: &SessionFilter,
+) -> Option<&'a Session> {
+    sessions
+        .iter()
+        .filter(|session| session.now_playing_item.is_some())
+        .filter(|session| filter.allows(&session.client, &session.device_name))
+        .filter_map(|session| {
+            let priority = usernames
+                .iter()
+                .position(|username| session.user_name.as_ref() == Some(username))?;
+            Some((priority, session))
+        })
+        .min_by_key(|(priority, _)| *priority)
+        .map(|(_, session)| session)
+}
+
+/// Finds who Discord should be showing, the same way [`get_now_playing`] does.
+pub async fn get_choice(
+    url: String,
+    api_key: String,
+    usernames: Vec<String>,
+    filter: SessionFilter,
+) -> Result<Choice, String> {
+    let sessions = get_all_sessions(&url, &api_key).await?;
+
+    Ok(decide(&sessions, &usernames, &filter))
+}
+
+fn decide(sessions: &[Session], usernames: &[String], filter: &SessionFilter) -> Choice {
+    let playing = |session: &&Session| session.now_playing_item.is_some();
+
+    let chosen = match choose(sessions, usernames, filter) {
+        Some(chosen) => chosen,
+        None => {
+            let tracked = sessions.iter().filter(playing).any(|session| {
+                session
+                    .user_name
+                    .as_ref()
+                    .is_some_and(|name| usernames.contains(name))
+            });
+
+            return match tracked {
+                true => Choice::Filtered,
+                false => Choice::Nothing,
+            };
+        }
+    };
+
+    let user = chosen.user_name.clone().unwrap_or_default();
+
+    // jellyfin-rpc goes with the first session of the user, which might be one of these
+    let ambiguous = sessions.iter().filter(playing).any(|session| {
+        session.user_name == chosen.user_name
+            && !filter.allows(&session.client, &session.device_name)
+    });
+
+    match ambiguous {
+        true => Choice::Session {
+            user,
+            id: chosen.id.clone(),
+            device: chosen.device_name.clone(),
+        },
+        false => Choice::User(user),
+    }
+}
+
+/// Finds what the tracked users are playing.
+pub async fn get_now_playing(
+    url: String,
+    api_key: String,
+    usernames: Vec<String>,
+    filter: SessionFilter,
+) -> Result<Option<NowPlaying>, String> {
+    let sessions = get_all_sessions(&url, &api_key).await?;
+
+    Ok(choose(&sessions, &usernames, &filter)
+        .cloned()
+        .and_then(now_playing))
+}
+
+/// Finds what's playing on the session with this ID.
+pub async fn get_now_playing_on(
+    url: String,
+    api_key: String,
+    session_id: String,
+) -> Result<Option<NowPlaying>, String> {
+    let sessions = get_all_sessions(&url, &api_key).await?;
+
+    Ok(sessions
+        .into_iter()
+        .find(|session| session.id == session_id)
+        .and_then(now_playing))
+}
+
+fn now_playing(session: Session) -> Option<NowPlaying> {
+    let mut now_playing = NowPlaying::from(session.now_playing_item?);
+
+    if let Some(play_state) = session.play_state {
+        now_playing.position = play_state.position_ticks.unwrap_or_default() / TICKS_PER_SECOND;
+        now_playing.paused = play_state.is_paused.unwrap_or_default();
+    }
+
+    Some(now_playing)
+}
+
+pub async fn get_cover(url: String, api_key: String, item_id: String) -> Result<Vec<u8>, String> {
+    jellyfin::Client::new(&url, Some(&api_key))
+        .get(&format!("/Items/{}/Images/Primary", item_id))
+        .query(&[("maxHeight", "240")])
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .bytes()
+        .await
+        .map(|bytes| bytes.to_vec())
+        .map_err(|err| err.to_string())
+}
+
+/// Formats seconds as `h:mm:ss`, or `m:ss` for anything shorter than an hour.
+pub fn format_time(seconds: u64) -> String {
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gui_config::SessionMatch;
+
+    fn session(id: &str, user: &str, device: &str, playing: bool) -> Session {
+        Session {
+            id: id.to_string(),
+            user_name: Some(user.to_string()),
+            client: "Jellyfin Web".to_string(),
+            device_name: device.to_string(),
+            now_playing_item: playing.then(|| Item {
+                id: format!("item-{}", id),
+                name: "Something".to_string(),
+                media_type: "Movie".to_string(),
+                series_name: None,
+                parent_index_number: None,
+                index_number: None,
+                artists: None,
+                album: None,
+                production_year: None,
+                run_time_ticks: None,
+            }),
+            play_state: None,
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn excluding(device: &str) -> SessionFilter {
+        SessionFilter {
+            pinned: Vec::new(),
+            excluded: vec![SessionMatch::Device(device.to_string())],
+        }
+    }
+
+    #[test]
+    fn choose_goes_by_the_order_of_the_users() {
+        let sessions = [
+            session("1", "bob", "Desktop", true),
+            session("2", "alice", "Laptop", true),
+        ];
+
+        let chosen = choose(
+            &sessions,
+            &names(&["alice", "bob"]),
+            &SessionFilter::default(),
+        );
+        assert_eq!(chosen.map(|session| session.id.as_str()), Some("2"));
+    }
+
+    #[test]
+    fn choose_skips_idle_untracked_and_filtered_sessions() {
+        let sessions = [
+            session("1", "alice", "Desktop", false),
+            session("2", "stranger", "Desktop", true),
+            session("3", "alice", "Kids TV", true),
+            session("4", "bob", "Desktop", true),
+        ];
+
+        let chosen = choose(&sessions, &names(&["alice", "bob"]), &excluding("Kids TV"));
+        assert_eq!(chosen.map(|session| session.id.as_str()), Some("4"));
+    }
+
+    #[test]
+    fn decide_tells_nothing_playing_from_everything_filtered() {
+        let usernames = names(&["alice"]);
+        let filter = excluding("Kids TV");
+
+        let idle = [session("1", "alice", "Kids TV", false)];
+        assert_eq!(decide(&idle, &usernames, &filter), Choice::Nothing);
+
+        let filtered = [session("1", "alice", "Kids TV", true)];
+        assert_eq!(decide(&filtered, &usernames, &filter), Choice::Filtered);
+
+        let untracked = [session("1", "stranger", "Desktop", true)];
+        assert_eq!(decide(&untracked, &usernames, &filter), Choice::Nothing);
+    }
+
+    #[test]
+    fn decide_follows_the_session_when_the_user_also_plays_on_a_filtered_one() {
+        let sessions = [
+            session("1", "alice", "Kids TV", true),
+            session("2", "alice", "Desktop", true),
+        ];
+
+        assert_eq!(
+            decide(&sessions, &names(&["alice"]), &excluding("Kids TV")),
+            Choice::Session {
+                user: "alice".to_string(),
+                id: "2".to_string(),
+                device: "Desktop".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn decide_leaves_unambiguous_users_to_jellyfin_rpc() {
+        let sessions = [
+            session("1", "bob", "Kids TV", true),
+            session("2", "alice", "Desktop", true),
+            session("3", "alice", "Kids TV", false),
+        ];
+
+        assert_eq!(
+            decide(&sessions, &names(&["bob", "alice"]), &excluding("Kids TV")),
+            Choice::User("alice".to_string())
+        );
+    }
+
+    #[test]
+    fn decide_only_shows_pinned_sessions() {
+        let sessions = [
+            session("1", "alice", "Desktop", true),
+            session("2", "alice", "Living room", true),
+        ];
+        let filter = SessionFilter {
+            pinned: vec![SessionMatch::Device("Living room".to_string())],
+            excluded: Vec::new(),
+        };
+
+        assert_eq!(
+            decide(&sessions, &names(&["alice"]), &filter).user(),
+            Some("alice")
+        );
+        assert!(matches!(
+            decide(&sessions, &names(&["alice"]), &filter),
+            Choice::Session { id, .. } if id == "2"
+        ));
+    }
+}
@@ -1,45 +1,603 @@
+use crate::config_file;
+use crate::event_log::{Entry, Level};
+use crate::gui_config::{self, GuiConfig, SessionFilter};
+use crate::ipc::{self, Request};
+use crate::libraries;
+use crate::now_playing::{self, Choice, NowPlaying};
 use crate::VERSION;
-use discord_rich_presence::DiscordIpcClient;
+use discord_rich_presence::activity::{Activity, Assets, Timestamps};
+use discord_rich_presence::{DiscordIpc, DiscordIpcClient};
 use jellyfin_rpc::{prelude::*, core::config::Username};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+pub const DISCORD_CONNECTED: &str = "Discord connected";
+pub const DISCORD_DISCONNECTED: &str = "Discord disconnected";
+pub const SERVER_CRASHED: &str = "Server crashed";
+pub const CONFIG_ERROR: &str = "Config file has errors";
+const PRESENCE_HIDDEN: &str = "Presence hidden";
+const FOLLOWING_SESSION: &str = "Following one session";
+
+/// Longest we wait between attempts to reach Discord or restart the presence loop.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// A presence loop that survived this long is considered healthy again,
+/// so the next crash starts over with a short backoff.
+const STABLE_AFTER: Duration = Duration::from_secs(300);
+
+/// How often the sessions are checked for whether Discord shows the right user.
+const CHOICE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The server's ends of the channels to the GUI.
+pub struct Channels {
+    pub tx: mpsc::Sender<Event>,
+    pub rx: mpsc::Receiver<Command>,
+    /// Restarts the presence loop, switching to another config file if one is given.
+    pub restart: UnboundedReceiver<Option<String>>,
+}
 
 pub async fn run(
     config_path: String,
+    config: Config,
+    image_urls: Option<String>,
+    channels: Channels,
+) {
+    tokio::spawn(supervise(config_path, config, image_urls, channels));
+}
+
+/// Runs the presence loop without a window, printing its events to stdout.
+pub async fn run_headless(
+    config_path: String,
+    image_urls: Option<String>,
+    listener: ipc::Listener,
+) {
+    let config = config_file::load(&config_path).unwrap_or_else(|err| {
+        eprintln!("Error loading config {}", err);
+        std::process::exit(1)
+    });
+
+    let (tx, events) = mpsc::channel();
+    let (commands, rx) = mpsc::channel();
+    // Kept around for `ctl restart`, which is also how a fixed config gets picked up
+    let (restarts, restart) = tokio::sync::mpsc::unbounded_channel();
+
+    let status = Arc::new(Mutex::new(ipc::Status::default()));
+    let logged_status = status.clone();
+
+    std::thread::spawn(move || {
+        for event in events {
+            let entry = Entry::from(event);
+            match entry.level {
+                Level::Error => eprintln!("{}", entry),
+                _ => println!("{}", entry),
+            }
+            logged_status.lock().unwrap().update(&entry);
+        }
+    });
+
+    let control = ipc::listen(listener, status, move |request| {
+        let command = match request {
+            Request::Start => Command::Start,
+            Request::Stop => Command::Stop,
+            Request::ReloadConfig => Command::ReloadConfig,
+            Request::Restart => return restarts.send(None).map_err(|err| err.to_string()),
+            _ => return Err("Not supported in headless mode".to_string()),
+        };
+        commands.send(command).map_err(|err| err.to_string())
+    });
+
+    tokio::select! {
+        _ = supervise(config_path, config, image_urls, Channels { tx, rx, restart }) => (),
+        Err(err) = control => eprintln!("Control socket closed: {}", err),
+        _ = tokio::signal::ctrl_c() => println!("Shutting down"),
+    }
+}
+
+/// Keeps the presence loop running, restarting it with a fresh Discord client
+/// whenever it crashes or the GUI asks for it.
+async fn supervise(
+    mut config_path: String,
     mut config: Config,
     image_urls: Option<String>,
-    tx: mpsc::Sender<Event>,
-    rx: mpsc::Receiver<Command>,
+    channels: Channels,
 ) {
-    tokio::spawn(async move {
-        if config.jellyfin.api_key.is_empty() {
-            tx.send(Event::Error("Jellyfin API key not set".to_string(), "".to_string()))
-                .unwrap();
-            return;
-        } else if config.jellyfin.url.is_empty() {
-            tx.send(Event::Error("Jellyfin URL not set".to_string(), "".to_string()))
-                .unwrap();
-            return;
-        } else if config.jellyfin.username == Username::String("".to_string()) {
-            tx.send(Event::Error("Jellyfin Username not set".to_string(), "".to_string()))
+    let Channels {
+        tx,
+        rx,
+        mut restart,
+    } = channels;
+
+    // The presence loop takes ownership of its receiver, so every run gets its own
+    // channel and commands from the GUI are passed on to whichever one is current
+    let current: Arc<Mutex<Option<mpsc::Sender<Command>>>> = Arc::new(Mutex::new(None));
+    let forward = current.clone();
+    // Reloading the file would undo picking a user for the presence loop, so we hear about it
+    let (reloaded_tx, mut reloaded) = tokio::sync::mpsc::unbounded_channel();
+    let (started_tx, mut started) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        for command in rx {
+            let reload = matches!(command, Command::ReloadConfig);
+
+            match forward.lock().unwrap().as_ref() {
+                Some(sender) => {
+                    sender.send(command).ok();
+                }
+                // Nothing is running that could be started, so it takes a new run
+                None if matches!(command, Command::Start) => {
+                    started_tx.send(()).ok();
+                }
+                None => (),
+            }
+
+            if reload {
+                reloaded_tx.send(()).ok();
+            }
+        }
+    });
+
+    let mut crashes: u32 = 0;
+    let mut backoff = Duration::from_secs(1);
+    // Who the presence loop is limited to, if anyone
+    let mut showing = Choice::Nothing;
+
+    loop {
+        while reloaded.try_recv().is_ok() {}
+        while started.try_recv().is_ok() {}
+
+        // Pick up whatever was fixed in the config since the last run
+        let checked = match config_file::load(&config_path) {
+            Ok(new_config) => {
+                config = new_config;
+                validate(&config).map_err(|err| Event::Error(err.to_string(), "".to_string()))
+            }
+            Err(err) => Err(Event::Error(CONFIG_ERROR.to_string(), err.to_string())),
+        };
+
+        if let Err(event) = checked {
+            tx.send(event).unwrap();
+
+            // Nothing to do until the config is fixed and we're told to try again
+            match wait_idle(&mut restart, &mut started).await {
+                Some(new_path) => {
+                    config_path = new_path.unwrap_or(config_path);
+                    continue;
+                }
+                None => return,
+            }
+        }
+
+        if let Err(err) = reconcile_libraries(&config_path, &mut config).await {
+            tx.send(Event::Error(
+                "Couldn't update the library blacklist".to_string(),
+                err,
+            ))
+            .unwrap();
+        }
+
+        let usernames = usernames(&config);
+        let filter = GuiConfig::load(&config_path).sessions;
+
+        // jellyfin-rpc shows whichever tracked user it comes across first, so with
+        // several of them it only gets to see the one that should be shown
+        let selective = usernames.len() > 1 || filter != SessionFilter::default();
+
+        let choice = match selective {
+            true => now_playing::get_choice(
+                config.jellyfin.url.clone(),
+                config.jellyfin.api_key.clone(),
+                usernames.clone(),
+                filter.clone(),
+            )
+            .await
+            .unwrap_or(Choice::Nothing),
+            false => Choice::Nothing,
+        };
+
+        if choice.user().is_some() {
+            showing = choice.clone();
+        }
+
+        // Without a presence loop there's nothing for Discord to show
+        if choice == Choice::Filtered {
+            tx.send(Event::Information(
+                PRESENCE_HIDDEN.to_string(),
+                "Only sessions that are excluded or not pinned are playing".to_string(),
+            ))
+            .unwrap();
+
+            tokio::select! {
+                _ = choice_changed(&config, &filter, &showing, true) => continue,
+                Some(new_path) = restart.recv() => {
+                    config_path = new_path.unwrap_or(config_path);
+                    continue;
+                }
+            }
+        }
+
+        if !selective
+            || !showing
+                .user()
+                .is_some_and(|user| usernames.iter().any(|name| name == user))
+        {
+            showing = Choice::Nothing;
+        }
+
+        let mut presence_config = config.clone();
+        if let Some(user) = showing.user() {
+            presence_config.jellyfin.username = Username::String(user.to_string());
+        }
+
+        let application_id = config
+            .discord
+            .clone()
+            .and_then(|discord| discord.application_id)
+            .unwrap_or(String::from("1053747938519679018"));
+
+        let mut rich_presence_client = tokio::select! {
+            client = wait_for_discord(&tx, &application_id) => client,
+            Some(new_path) = restart.recv() => {
+                config_path = new_path.unwrap_or(config_path);
+                continue;
+            }
+        };
+
+        let (command_tx, command_rx) = mpsc::channel();
+        *current.lock().unwrap() = Some(command_tx);
+
+        let started = Instant::now();
+
+        let crashed = tokio::select! {
+            crashed = async {
+                match &showing {
+                    // jellyfin-rpc would show whichever of the user's sessions comes first
+                    Choice::Session { user, id, device } => {
+                        tx.send(Event::Information(
+                            FOLLOWING_SESSION.to_string(),
+                            format!("{} is also playing on excluded sessions, showing {}", user, device),
+                        ))
+                        .unwrap();
+
+                        follow(&tx, command_rx, &mut rich_presence_client, &config, id).await.is_err()
+                    }
+                    // Start up the client connection, so that we can actually send and receive stuff
+                    _ => jellyfin_rpc::presence_loop(tx.clone(), Some(command_rx), &mut rich_presence_client, &config_path, &mut presence_config, VERSION.unwrap_or("0.0.0"), image_urls.clone()).await.is_err(),
+                }
+            } => crashed,
+            _ = choice_changed(&config, &filter, &showing, false), if selective => {
+                *current.lock().unwrap() = None;
+                continue;
+            }
+            Some(()) = reloaded.recv(), if showing.user().is_some() => {
+                *current.lock().unwrap() = None;
+                continue;
+            }
+            Some(new_path) = restart.recv() => {
+                config_path = new_path.unwrap_or(config_path);
+                *current.lock().unwrap() = None;
+                crashes = 0;
+                backoff = Duration::from_secs(1);
+                tx.send(Event::Information("Restarting".to_string(), "".to_string()))
+                    .unwrap();
+                continue;
+            }
+        };
+
+        *current.lock().unwrap() = None;
+
+        if !crashed {
+            tx.send(Event::Information("Stopped".to_string(), "".to_string()))
                 .unwrap();
-            return;
+
+            match wait_idle(&mut restart, &mut started).await {
+                Some(new_path) => {
+                    config_path = new_path.unwrap_or(config_path);
+                    continue;
+                }
+                None => return,
+            }
+        }
+
+        if started.elapsed() > STABLE_AFTER {
+            crashes = 0;
+            backoff = Duration::from_secs(1);
+        }
+
+        crashes += 1;
+        tx.send(Event::Error(
+            SERVER_CRASHED.to_string(),
+            format!(
+                "{} {} time(s), restarting in {}s",
+                SERVER_CRASHED,
+                crashes,
+                backoff.as_secs()
+            ),
+        ))
+        .unwrap();
+
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => (),
+            Some(new_path) = restart.recv() => {
+                config_path = new_path.unwrap_or(config_path);
+                crashes = 0;
+                backoff = Duration::from_secs(1);
+            }
         }
 
-        let mut rich_presence_client = DiscordIpcClient::new(
-            config
-                .discord
-                .clone()
-                .and_then(|discord| discord.application_id)
-                .unwrap_or(String::from("1053747938519679018"))
-                .as_str(),
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+/// Waits for a restart or for Start while no presence loop is running. Gives
+/// the config file to switch to, if any, or `None` once nobody can ask anymore.
+async fn wait_idle(
+    restart: &mut UnboundedReceiver<Option<String>>,
+    started: &mut UnboundedReceiver<()>,
+) -> Option<Option<String>> {
+    tokio::select! {
+        Some(new_path) = restart.recv() => Some(new_path),
+        Some(()) = started.recv() => Some(None),
+        else => None,
+    }
+}
+
+/// Resolves once the sessions call for showing something other than `showing`,
+/// or for hiding the presence, or for bringing it back when it's `hidden`.
+async fn choice_changed(config: &Config, filter: &SessionFilter, showing: &Choice, hidden: bool) {
+    loop {
+        tokio::time::sleep(CHOICE_INTERVAL).await;
+
+        let choice = now_playing::get_choice(
+            config.jellyfin.url.clone(),
+            config.jellyfin.api_key.clone(),
+            usernames(config),
+            filter.clone(),
         )
-        .expect(
-            "Failed to create Discord RPC client, discord is down or the Client ID is invalid.",
+        .await;
+
+        match choice {
+            Ok(Choice::Nothing) if hidden => return,
+            Ok(Choice::Filtered) if !hidden => return,
+            Ok(choice) if choice.user().is_some() && (hidden || choice != *showing) => return,
+            _ => (),
+        }
+    }
+}
+
+/// Shows one session on Discord until told to stop, for when jellyfin-rpc would
+/// pick another session of the same user. Covers less than the presence loop does.
+async fn follow(
+    tx: &mpsc::Sender<Event>,
+    commands: mpsc::Receiver<Command>,
+    client: &mut DiscordIpcClient,
+    config: &Config,
+    session_id: &str,
+) -> Result<(), String> {
+    client.connect().map_err(|err| err.to_string())?;
+
+    let mut shown: Option<(NowPlaying, Instant)> = None;
+
+    loop {
+        match commands.try_recv() {
+            Ok(Command::Stop) | Err(mpsc::TryRecvError::Disconnected) => {
+                client.clear_activity().ok();
+                client.close().ok();
+                return Ok(());
+            }
+            _ => (),
+        }
+
+        let now_playing = now_playing::get_now_playing_on(
+            config.jellyfin.url.clone(),
+            config.jellyfin.api_key.clone(),
+            session_id.to_string(),
+        )
+        .await;
+
+        match now_playing {
+            Ok(now_playing) if outdated(shown.as_ref(), now_playing.as_ref()) => {
+                match &now_playing {
+                    Some(now_playing) => {
+                        let image = image_url(config, now_playing);
+                        client.set_activity(activity(now_playing, image.as_deref()))
+                    }
+                    None => client.clear_activity(),
+                }
+                .map_err(|err| err.to_string())?;
+
+                shown = now_playing.map(|now_playing| (now_playing, Instant::now()));
+            }
+            Ok(_) => (),
+            Err(err) => {
+                tx.send(Event::Error("Couldn't get the session".to_string(), err))
+                    .unwrap();
+            }
+        }
+
+        tokio::time::sleep(CHOICE_INTERVAL).await;
+    }
+}
+
+/// Discord only allows a few updates at a time, so the activity is left alone
+/// while playback carries on the way it says.
+fn outdated(shown: Option<&(NowPlaying, Instant)>, now_playing: Option<&NowPlaying>) -> bool {
+    let ((shown, shown_at), now_playing) = match (shown, now_playing) {
+        (Some(shown), Some(now_playing)) => (shown, now_playing),
+        (None, None) => return false,
+        _ => return true,
+    };
+
+    let expected = match shown.paused {
+        true => shown.position,
+        false => shown.position + shown_at.elapsed().as_secs(),
+    };
+
+    shown.item_id != now_playing.item_id
+        || shown.paused != now_playing.paused
+        || expected.abs_diff(now_playing.position) > CHOICE_INTERVAL.as_secs() * 2
+}
+
+/// Same as the presence loop, Discord fetches the cover straight from the server.
+fn image_url(config: &Config, now_playing: &NowPlaying) -> Option<String> {
+    let enabled = config
+        .images
+        .as_ref()
+        .is_some_and(|images| images.enable_images.is_some_and(|enabled| enabled));
+
+    enabled.then(|| {
+        format!(
+            "{}/Items/{}/Images/Primary",
+            config.jellyfin.url.trim_end_matches('/'),
+            now_playing.item_id
+        )
+    })
+}
+
+fn activity<'a>(now_playing: &'a NowPlaying, image: Option<&'a str>) -> Activity<'a> {
+    let mut activity = Activity::new().details(&now_playing.title);
+
+    if let Some(image) = image {
+        activity = activity.assets(
+            Assets::new()
+                .large_image(image)
+                .large_text(&now_playing.title),
         );
+    }
 
-        // Start up the client connection, so that we can actually send and receive stuff
-        jellyfin_rpc::presence_loop(tx.clone(), Some(rx), &mut rich_presence_client, &config_path, &mut config, VERSION.unwrap_or("0.0.0"), image_urls).await.unwrap_or_else(|_| {
-            tx.send(Event::Error("Server Error".to_string(), "Server crashed".to_string())).unwrap()
-        });
-    });
+    if !now_playing.subtitle.is_empty() {
+        activity = activity.state(&now_playing.subtitle);
+    }
+
+    if !now_playing.paused {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        activity = activity
+            .timestamps(Timestamps::new().start(now.saturating_sub(now_playing.position) as i64));
+    }
+
+    activity
+}
+
+/// Follows renamed libraries on the server, jellyfin-rpc only knows the names of
+/// the libraries it should hide. The window does the same when it lists them,
+/// but headless mode has nobody opening it.
+async fn reconcile_libraries(config_path: &str, config: &mut Config) -> Result<(), String> {
+    let names = config
+        .jellyfin
+        .blacklist
+        .clone()
+        .and_then(|blacklist| blacklist.libraries)
+        .unwrap_or_default();
+
+    if names.is_empty() {
+        return Ok(());
+    }
+
+    let libraries = libraries::get_libraries(
+        config.jellyfin.url.clone(),
+        config.jellyfin.api_key.clone(),
+        usernames(config),
+    )
+    .await;
+
+    // Nothing to compare with, the next run tries again
+    let libraries: Vec<(String, String)> = match libraries {
+        Ok(libraries) => libraries
+            .into_iter()
+            .map(|library| (library.id, library.name))
+            .collect(),
+        Err(_) => return Ok(()),
+    };
+
+    let mut gui = GuiConfig::load(config_path);
+    let known = gui_config::reconcile_libraries(&gui.blacklisted_libraries, &names, &[]);
+    let known_names: Vec<String> = known.iter().map(|library| library.name.clone()).collect();
+    let reconciled = gui_config::reconcile_libraries(&known, &known_names, &libraries);
+
+    if reconciled == gui.blacklisted_libraries {
+        return Ok(());
+    }
+
+    gui.blacklisted_libraries = reconciled;
+    if let Some(blacklist) = config.jellyfin.blacklist.as_mut() {
+        blacklist.libraries = Some(
+            gui.blacklisted_libraries
+                .iter()
+                .map(|library| library.name.clone())
+                .collect(),
+        );
+    }
+
+    let contents = gui_config::merge(config, &gui).map_err(|err| err.to_string())?;
+    config_file::write(config_path, &contents).map_err(|err| err.to_string())
+}
+
+fn usernames(config: &Config) -> Vec<String> {
+    match &config.jellyfin.username {
+        Username::Vec(usernames) => usernames.to_vec(),
+        Username::String(username) => vec![username.to_string()],
+    }
+}
+
+/// How many crashes in a row a [`SERVER_CRASHED`] event is about.
+pub fn crash_count(message: &str) -> Option<u32> {
+    message
+        .strip_prefix(SERVER_CRASHED)?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn validate(config: &Config) -> Result<(), &'static str> {
+    if config.jellyfin.api_key.is_empty() {
+        Err("Jellyfin API key not set")
+    } else if config.jellyfin.url.is_empty() {
+        Err("Jellyfin URL not set")
+    } else if config.jellyfin.username == Username::String("".to_string()) {
+        Err("Jellyfin Username not set")
+    } else {
+        Ok(())
+    }
+}
+
+/// Discord might not be running yet, so keep knocking until it answers.
+async fn wait_for_discord(tx: &mpsc::Sender<Event>, application_id: &str) -> DiscordIpcClient {
+    let mut backoff = Duration::from_secs(1);
+
+    loop {
+        match connect(application_id) {
+            Ok(client) => {
+                tx.send(Event::Information(DISCORD_CONNECTED.to_string(), "".to_string()))
+                    .unwrap();
+                return client;
+            }
+            Err(err) => {
+                tx.send(Event::Error(
+                    DISCORD_DISCONNECTED.to_string(),
+                    format!(
+                        "Couldn't connect to Discord, is it running and is the application ID valid? Retrying in {}s ({})",
+                        backoff.as_secs(),
+                        err
+                    ),
+                ))
+                .unwrap();
+
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+        }
+    }
+}
+
+/// Makes sure Discord accepts a connection with this application ID, then hands
+/// a fresh client to the presence loop, which does its own connecting.
+fn connect(application_id: &str) -> Result<DiscordIpcClient, String> {
+    let mut probe = DiscordIpcClient::new(application_id).map_err(|err| err.to_string())?;
+    probe.connect().map_err(|err| err.to_string())?;
+    probe.close().ok();
+
+    DiscordIpcClient::new(application_id).map_err(|err| err.to_string())
 }
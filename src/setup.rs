@@ -0,0 +1,199 @@
+use crate::jellyfin;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Server,
+    Credentials,
+    Users,
+    Discord,
+}
+
+impl Step {
+    pub fn number(&self) -> u8 {
+        match self {
+            Step::Server => 1,
+            Step::Credentials => 2,
+            Step::Users => 3,
+            Step::Discord => 4,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Setup {
+    pub server_name: String,
+    pub username: String,
+    pub password: String,
+    pub access_token: String,
+    pub users: Vec<String>,
+    pub selected_users: Vec<String>,
+    pub error: String,
+    pub busy: bool,
+}
+
+#[derive(Default)]
+pub struct QuickConnect {
+    pub secret: String,
+    pub code: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Authentication {
+    pub username: String,
+    pub access_token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AuthenticationResult {
+    user: User,
+    access_token: String,
+}
+
+impl From<AuthenticationResult> for Authentication {
+    fn from(result: AuthenticationResult) -> Self {
+        Self {
+            username: result.user.name,
+            access_token: result.access_token,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QuickConnectCode {
+    pub secret: String,
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct QuickConnectState {
+    authenticated: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct User {
+    name: String,
+}
+
+/// Checks that the URL points at a Jellyfin server and returns its name.
+pub async fn get_server_name(url: String) -> Result<String, String> {
+    let info: Value = jellyfin::Client::new(&url, None)
+        .get("/System/Info/Public")
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json()
+        .await
+        .map_err(|_| "Not a Jellyfin server".to_string())?;
+
+    info["ServerName"]
+        .as_str()
+        .map(|name| name.to_string())
+        .ok_or("Not a Jellyfin server".to_string())
+}
+
+pub async fn authenticate(
+    url: String,
+    username: String,
+    password: String,
+) -> Result<Authentication, String> {
+    let response = jellyfin::Client::new(&url, None)
+        .post("/Users/AuthenticateByName")
+        .json(&json!({
+            "Username": username,
+            "Pw": password,
+        }))
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Err("Wrong username or password".to_string());
+    }
+
+    let result: AuthenticationResult = response
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(result.into())
+}
+
+pub async fn initiate_quick_connect(url: String) -> Result<QuickConnectCode, String> {
+    let response = jellyfin::Client::new(&url, None)
+        .post("/QuickConnect/Initiate")
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+            return Err("Quick Connect is not enabled on this server".to_string())
+        }
+        _ => (),
+    }
+
+    response
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json()
+        .await
+        .map_err(|err| err.to_string())
+}
+
+/// Waits a few seconds and then asks the server if the code has been approved yet.
+pub async fn poll_quick_connect(url: String, secret: String) -> Result<bool, String> {
+    tokio::time::sleep(std::time::Duration::from_secs(3)).await;
+
+    let state: QuickConnectState = jellyfin::Client::new(&url, None)
+        .get("/QuickConnect/Connect")
+        .query(&[("secret", &secret)])
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|_| "Quick Connect code expired".to_string())?
+        .json()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(state.authenticated)
+}
+
+pub async fn authenticate_quick_connect(
+    url: String,
+    secret: String,
+) -> Result<Authentication, String> {
+    let result: AuthenticationResult = jellyfin::Client::new(&url, None)
+        .post("/Users/AuthenticateWithQuickConnect")
+        .json(&json!({
+            "Secret": secret,
+        }))
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    Ok(result.into())
+}
+
+/// Lists the names of the users on the server, this only works for administrators.
+pub async fn get_users(url: String, access_token: String) -> Result<Vec<String>, String> {
+    let users = crate::users::get_users(url, access_token).await?;
+
+    Ok(users.into_iter().map(|user| user.name).collect())
+}
@@ -0,0 +1,145 @@
+use tray_icon::menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem};
+use tray_icon::{Icon, TrayIcon, TrayIconBuilder};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrayEvent {
+    Start,
+    Stop,
+    ToggleWindow,
+    Quit,
+}
+
+/// Handle to the tray icon.
+///
+/// On Linux the icon lives on its own GTK thread, so status updates are sent
+/// over a channel. Everywhere else it's created on the main thread and driven
+/// by the same event loop as the window.
+pub struct Tray {
+    #[cfg(target_os = "linux")]
+    status: std::sync::mpsc::Sender<String>,
+    #[cfg(not(target_os = "linux"))]
+    icon: TrayIcon,
+    #[cfg(not(target_os = "linux"))]
+    status: MenuItem,
+}
+
+impl Tray {
+    #[cfg(target_os = "linux")]
+    pub fn new() -> Option<Self> {
+        use gtk::glib;
+
+        let (status_tx, status_rx) = std::sync::mpsc::channel::<String>();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+
+        std::thread::spawn(move || {
+            if gtk::init().is_err() {
+                ready_tx.send(false).ok();
+                return;
+            }
+
+            let (_icon, status) = match build() {
+                Ok(tray) => tray,
+                Err(_) => {
+                    ready_tx.send(false).ok();
+                    return;
+                }
+            };
+
+            ready_tx.send(true).ok();
+
+            glib::timeout_add_local(std::time::Duration::from_millis(250), move || {
+                while let Ok(text) = status_rx.try_recv() {
+                    status.set_text(text);
+                }
+                glib::ControlFlow::Continue
+            });
+
+            gtk::main();
+        });
+
+        match ready_rx.recv() {
+            Ok(true) => Some(Self { status: status_tx }),
+            _ => None,
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn new() -> Option<Self> {
+        let (icon, status) = build().ok()?;
+        Some(Self { icon, status })
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn set_status(&self, status: &str) {
+        self.status.send(format!("Status: {}", status)).ok();
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn set_status(&self, status: &str) {
+        self.status.set_text(format!("Status: {}", status));
+        self.icon
+            .set_tooltip(Some(format!("Jellyfin-RPC-Iced: {}", status)))
+            .ok();
+    }
+}
+
+fn build() -> Result<(TrayIcon, MenuItem), tray_icon::Error> {
+    let status = MenuItem::with_id("status", "Status: Not running", false, None);
+
+    let menu = Menu::new();
+    menu.append_items(&[
+        &status,
+        &PredefinedMenuItem::separator(),
+        &MenuItem::with_id("start", "Start", true, None),
+        &MenuItem::with_id("stop", "Stop", true, None),
+        &PredefinedMenuItem::separator(),
+        &MenuItem::with_id("toggle", "Show/Hide window", true, None),
+        &MenuItem::with_id("quit", "Quit", true, None),
+    ])?;
+
+    let icon = TrayIconBuilder::new()
+        .with_menu(Box::new(menu))
+        .with_tooltip("Jellyfin-RPC-Iced: Not running")
+        .with_icon(icon())
+        .build()?;
+
+    Ok((icon, status))
+}
+
+/// Draws a simple filled circle in Jellyfin purple, since we don't ship an icon file.
+fn icon() -> Icon {
+    const SIZE: u32 = 32;
+
+    let mut rgba = Vec::with_capacity((SIZE * SIZE * 4) as usize);
+    let center = SIZE as f32 / 2.0;
+
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let dx = x as f32 + 0.5 - center;
+            let dy = y as f32 + 0.5 - center;
+            let alpha = match (dx * dx + dy * dy).sqrt() <= center {
+                true => 255,
+                false => 0,
+            };
+            rgba.extend_from_slice(&[0xAA, 0x5C, 0xC3, alpha]);
+        }
+    }
+
+    Icon::from_rgba(rgba, SIZE, SIZE).expect("Tray icon has the wrong size")
+}
+
+/// Waits for the next click in the tray menu.
+pub async fn next_event() -> Option<TrayEvent> {
+    let event = tokio::task::spawn_blocking(|| MenuEvent::receiver().recv())
+        .await
+        .ok()?
+        .ok()?;
+
+    match event.id.0.as_str() {
+        "start" => Some(TrayEvent::Start),
+        "stop" => Some(TrayEvent::Stop),
+        "toggle" => Some(TrayEvent::ToggleWindow),
+        "quit" => Some(TrayEvent::Quit),
+        _ => None,
+    }
+}
@@ -0,0 +1,49 @@
+use crate::jellyfin;
+use serde::Deserialize;
+
+/// A user account on the Jellyfin server.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ServerUser {
+    pub id: String,
+    pub name: String,
+    pub primary_image_tag: Option<String>,
+}
+
+/// Lists the users on the server, this only works for administrators.
+pub async fn get_users(url: String, api_key: String) -> Result<Vec<ServerUser>, String> {
+    let response = jellyfin::Client::new(&url, Some(&api_key))
+        .get("/Users")
+        .send()
+        .await
+        .map_err(|err| err.to_string())?;
+
+    match response.status() {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => {
+            return Err("This API key isn't allowed to list users".to_string())
+        }
+        _ => (),
+    }
+
+    response
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .json()
+        .await
+        .map_err(|err| err.to_string())
+}
+
+pub async fn get_avatar(url: String, api_key: String, user_id: String) -> Result<Vec<u8>, String> {
+    jellyfin::Client::new(&url, Some(&api_key))
+        .get(&format!("/Users/{}/Images/Primary", user_id))
+        .query(&[("maxHeight", "48")])
+        .send()
+        .await
+        .map_err(|err| err.to_string())?
+        .error_for_status()
+        .map_err(|err| err.to_string())?
+        .bytes()
+        .await
+        .map(|bytes| bytes.to_vec())
+        .map_err(|err| err.to_string())
+}
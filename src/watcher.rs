@@ -0,0 +1,67 @@
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+/// Editors tend to write a file in several steps, so changes that arrive this
+/// close together are reported once.
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+pub struct ConfigWatcher {
+    // Dropping the watcher stops it, so it has to live as long as the receiver
+    _watcher: RecommendedWatcher,
+    rx: UnboundedReceiver<()>,
+}
+
+impl ConfigWatcher {
+    /// Watches the directory of the config file rather than the file itself,
+    /// since a lot of editors save by replacing the file.
+    pub fn new(config_path: &str) -> notify::Result<Self> {
+        let config_path = PathBuf::from(config_path);
+        let dir = config_path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+            .unwrap_or(PathBuf::from("."));
+        let file_name = config_path.file_name().map(|name| name.to_os_string());
+
+        let (tx, rx) = unbounded_channel();
+
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(_) => return,
+                };
+
+                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    return;
+                }
+
+                if event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name().map(|name| name.to_os_string()) == file_name)
+                {
+                    tx.send(()).ok();
+                }
+            })?;
+
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+        Ok(Self {
+            _watcher: watcher,
+            rx,
+        })
+    }
+
+    /// Waits for the next change to the config file.
+    pub async fn changed(&mut self) -> Option<()> {
+        self.rx.recv().await?;
+
+        tokio::time::sleep(DEBOUNCE).await;
+        while self.rx.try_recv().is_ok() {}
+
+        Some(())
+    }
+}
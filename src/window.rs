@@ -1,19 +1,35 @@
+use crate::config_file::{self, Backup, ConfigError};
+use crate::connection::{self, ConnectionError, ConnectionInfo, KeyStatus};
+use crate::discovery::{self, Server};
+use crate::event_log::{self, Entry, Level, Log};
+use crate::gui_config::{
+    self, BlacklistedLibrary, GuiConfig, PaletteColor, SessionMatch, ThemeChoice, TrackedUser,
+};
+use crate::ipc::{self, Request};
+use crate::libraries::{self, ServerLibrary};
+use crate::now_playing::{self, NowPlaying, SessionInfo};
 use crate::server;
+use crate::setup::{self, Authentication, QuickConnect, QuickConnectCode, Setup, Step};
+use crate::tray::{self, Tray, TrayEvent};
+use crate::users::{self, ServerUser};
+use crate::watcher::ConfigWatcher;
 use crate::Args;
-use clap::Parser;
-use iced::widget::{button, checkbox, column, container, row, text, text_input};
+use iced::futures::SinkExt;
+use iced::widget::{
+    button, checkbox, column, container, image, pick_list, progress_bar, radio, row, scrollable,
+    text, text_input,
+};
 use iced::{executor, Alignment, Length};
 use iced::{Application, Command, Element, Theme};
 use jellyfin_rpc::prelude::{
     Command as RpcCommand,
     config::{
-        get_config_path, Blacklist, Config, Discord, Images, Imgur, Username,
+        Blacklist, Config, Discord, Display, Images, Imgur, Music, Username,
     }
 };
 use jellyfin_rpc::services::jellyfin::MediaType;
 use jellyfin_rpc::prelude::{Event, config::Button};
-use serde_json::Value;
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc, Mutex};
 
 #[derive(Debug, Clone)]
 pub enum Message {
@@ -21,7 +37,11 @@ pub enum Message {
     ReloadConfig,
     Start,
     Stop,
-    Update,
+    Restart,
+    Event(Entry),
+    Ipc(Request),
+    ConfigChanged,
+    Tick,
     UpdateUrl(String),
     UpdateApiKey(String),
     UpdateApplicationId(String),
@@ -35,15 +55,77 @@ pub enum Message {
     Images(bool),
     Imgur(bool),
     ImgurClientId(String),
-    UpdateLibraries(Vec<String>),
+    UpdateLibraries(Result<Vec<ServerLibrary>, String>),
     ToggleLibrary(Library, bool),
+    RemoveStaleLibrary(String),
     SaveSettings,
+    SetupCheckServer,
+    SetupServerChecked(Result<String, String>),
+    SetupUsername(String),
+    SetupPassword(String),
+    SetupLogin,
+    SetupAuthenticated(Result<Authentication, String>),
+    SetupUsers(Result<Vec<String>, String>),
+    SetupToggleUser(String, bool),
+    SetupFinish,
+    QuickConnectStart,
+    QuickConnectInitiated(Result<QuickConnectCode, String>),
+    QuickConnectPolled(String, Result<bool, String>),
+    QuickConnectAuthenticated(Result<Authentication, String>),
+    QuickConnectCancel,
+    Discover,
+    Discovered(Result<Vec<Server>, String>),
+    SelectServer(Server),
+    Tray(TrayEvent),
+    CloseRequested,
+    SelectTheme(ThemeChoice),
+    UpdatePalette(PaletteColor, String),
+    ToggleMusicField(MusicField, bool),
+    MoveMusicFieldUp(MusicField),
+    MoveMusicFieldDown(MusicField),
+    UpdateSeparator(String),
+    UpdateNowPlaying(Result<Option<NowPlaying>, String>),
+    UpdateCover(String, Result<Vec<u8>, String>),
+    LogLevel(Level),
+    LogFilter(String),
+    ExportLog(event_log::Format),
+    SelectBackup(Backup),
+    RestoreBackup,
+    SaveAndLeave,
+    DiscardChanges,
+    CancelLeave,
+    TestConnection,
+    ConnectionTested(Result<ConnectionInfo, ConnectionError>),
+    ServerUsers(Result<Vec<ServerUser>, String>),
+    UpdateAvatar(String, Result<Vec<u8>, String>),
+    ToggleUsername(String, bool),
+    ToggleUserEnabled(String, bool),
+    MoveUsernameUp(String),
+    MoveUsernameDown(String),
+    RefreshSessions,
+    Sessions(Result<Vec<SessionInfo>, String>),
+    PinSession(SessionMatch, bool),
+    ExcludeSession(SessionMatch, bool),
+    RemoveSessionRule(SessionMatch),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Panel {
     Main,
     Settings(Setting),
+    Setup(Step),
+    Log,
+    Repair,
+}
+
+/// Where to go once the unsaved changes prompt is answered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Leaving {
+    Panel(Panel),
+    Quit,
+    Reload,
+    /// Another launch asked for a different config file
+    Switch(String),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -54,6 +136,10 @@ pub enum Setting {
     Users,
     Images,
     Libraries,
+    QuickConnect,
+    Appearance,
+    Music,
+    Sessions,
 }
 
 pub struct Gui {
@@ -65,17 +151,61 @@ pub struct Gui {
     custom_buttons: bool,
     buttons: Buttons,
     image_options: ImageOptions,
+    music_display: MusicDisplay,
     new_username: String,
-    rx: mpsc::Receiver<Event>,
+    rx: Arc<Mutex<mpsc::Receiver<Event>>>,
     tx: mpsc::Sender<RpcCommand>,
     libraries: Vec<Library>,
     config_path: String,
     application_id: String,
+    setup: Setup,
+    quick_connect: QuickConnect,
+    servers: Vec<Server>,
+    discovering: bool,
+    server: Option<server::Channels>,
+    restart: tokio::sync::mpsc::UnboundedSender<Option<String>>,
+    crashes: u32,
+    image_urls: Option<String>,
+    tray: Option<Tray>,
+    visible: bool,
+    gui_config: GuiConfig,
+    theme: Theme,
+    now_playing: Option<NowPlaying>,
+    now_playing_updated: std::time::Instant,
+    cover: Option<(String, image::Handle)>,
+    log: Log,
+    log_level: Level,
+    log_filter: String,
+    log_export: String,
+    discord_connected: bool,
+    ipc_status: Arc<Mutex<ipc::Status>>,
+    /// Bound in `main`, the control subscription takes it once it starts
+    listener: Arc<Mutex<Option<ipc::Listener>>>,
+    notice: String,
+    /// Set while the file on disk can't be parsed, nothing gets saved until it's fixed
+    config_error: Option<ConfigError>,
+    backups: Vec<Backup>,
+    selected_backup: Option<Backup>,
+    /// The last saved state, compared against to tell if there's anything to save
+    saved: String,
+    leaving: Option<Leaving>,
+    connection_test: Option<Result<ConnectionInfo, ConnectionError>>,
+    testing_connection: bool,
+    server_users: Vec<ServerUser>,
+    server_users_error: String,
+    /// Keyed by user ID
+    avatars: std::collections::HashMap<String, image::Handle>,
+    sessions: Vec<SessionInfo>,
+    sessions_error: String,
+    libraries_error: String,
 }
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct Library {
+    id: String,
     name: String,
+    collection_type: Option<String>,
+    item_count: Option<u64>,
     enabled: bool,
 }
 
@@ -123,36 +253,611 @@ impl Gui {
             }
         }
     }
+
+    fn quick_connect_view(&self) -> iced::widget::Column<'_, Message> {
+        if !self.quick_connect.error.is_empty() {
+            return column![
+                text(self.quick_connect.error.clone()),
+                button("Try again")
+                    .on_press(Message::QuickConnectStart)
+                    .padding(5),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center);
+        }
+
+        match self.quick_connect.code.is_empty() {
+            true => column![],
+            false => column![
+                text("Enter this code under Quick Connect in another Jellyfin client:"),
+                text(self.quick_connect.code.clone()).size(40),
+                text("Waiting for approval..."),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+        }
+    }
+
+    fn servers_view(&self) -> iced::widget::Column<'_, Message> {
+        if self.discovering {
+            return column![text("Looking for servers...")];
+        }
+
+        self.servers.iter().fold(
+            column![].spacing(4).align_items(Alignment::Start),
+            |column: iced::widget::Column<'_, Message>, server| {
+                column.push(
+                    row![
+                        button("Use").on_press(Message::SelectServer(server.to_owned())),
+                        column![
+                            text(format!("{} ({})", server.name, server.address)),
+                            text(server.id.clone()).size(12),
+                        ],
+                    ]
+                    .spacing(3)
+                    .align_items(Alignment::Center),
+                )
+            },
+        )
+    }
+
+    fn config_error_view(&self) -> iced::widget::Column<'_, Message> {
+        match &self.config_error {
+            Some(err) => column![
+                text("Config file is invalid, settings are read-only")
+                    .style(self.theme.palette().danger),
+                text(err.to_string()).size(12),
+                button("Repair >")
+                    .on_press(Message::Open(Panel::Repair))
+                    .padding(5),
+            ]
+            .spacing(3)
+            .align_items(Alignment::Center),
+            None => column![],
+        }
+    }
+
+    /// Shows the raw config file with the line the parser choked on highlighted.
+    fn repair_view(&self) -> iced::widget::Column<'_, Message> {
+        let err = match &self.config_error {
+            Some(err) => err,
+            None => return column![text("The config file is fine")],
+        };
+
+        let danger = self.theme.palette().danger;
+
+        let lines = err.raw.lines().enumerate().fold(
+            column![].spacing(0).align_items(Alignment::Start),
+            |column: iced::widget::Column<'_, Message>, (index, line)| {
+                let number = index + 1;
+                let line = text(format!("{:>4} | {}", number, line))
+                    .size(12)
+                    .font(iced::Font::MONOSPACE);
+
+                match number == err.line {
+                    true => column.push(line.style(danger)).push(
+                        text(format!(
+                            "{:>4} | {}^",
+                            "",
+                            " ".repeat(err.column.saturating_sub(1))
+                        ))
+                        .size(12)
+                        .font(iced::Font::MONOSPACE)
+                        .style(danger),
+                    ),
+                    false => column.push(line),
+                }
+            },
+        );
+
+        column![
+            text("Config file is invalid").size(30),
+            text(err.to_string()).style(danger),
+            text("Fix the file in an editor, it's reloaded as soon as it's saved").size(12),
+            scrollable(lines).height(Length::Fill),
+        ]
+        .spacing(10)
+        .align_items(Alignment::Center)
+    }
+
+    fn connection_test_view(&self) -> iced::widget::Column<'_, Message> {
+        let test = match self.testing_connection {
+            true => button("Testing..."),
+            false => button("Test connection").on_press(Message::TestConnection),
+        };
+
+        let palette = self.theme.palette();
+
+        let result = match &self.connection_test {
+            Some(Ok(info)) => {
+                let key = match &info.key {
+                    KeyStatus::Valid => text("API key is valid").style(palette.success),
+                    KeyStatus::Rejected => text("API key was rejected (401)").style(palette.danger),
+                    KeyStatus::Unknown(status) => {
+                        text(format!("Couldn't check the API key ({})", status))
+                            .style(palette.danger)
+                    }
+                };
+
+                let users = match &info.missing_users {
+                    Some(missing) if !missing.is_empty() => {
+                        text(format!("Unknown users: {}", missing.join(", "))).style(palette.danger)
+                    }
+                    Some(_) => text("All users found"),
+                    None => text("The API key can't list users"),
+                };
+
+                column![
+                    text(format!("{} (Jellyfin {})", info.server_name, info.version)),
+                    text(format!("Latency: {}ms", info.latency.as_millis())),
+                    key,
+                    users,
+                ]
+            }
+            Some(Err(err)) => column![text(err.to_string()).style(palette.danger)],
+            None => column![],
+        };
+
+        column![
+            test.padding(5),
+            result.spacing(3).align_items(Alignment::Center)
+        ]
+        .spacing(3)
+        .align_items(Alignment::Center)
+    }
+
+    fn server_users_view(&self) -> iced::widget::Column<'_, Message> {
+        if !self.server_users_error.is_empty() {
+            return column![
+                text(format!(
+                    "Couldn't get the users from the server: {}",
+                    self.server_users_error
+                ))
+                .size(12),
+                text("You can still add usernames by hand below").size(12),
+            ]
+            .spacing(3)
+            .align_items(Alignment::Center);
+        }
+
+        self.server_users.iter().fold(
+            column![text("Users on the server:")]
+                .spacing(4)
+                .align_items(Alignment::Start),
+            |column: iced::widget::Column<'_, Message>, user| {
+                let avatar: Element<Message> = match self.avatars.get(&user.id) {
+                    Some(handle) => image(handle.clone())
+                        .width(Length::Fixed(24.0))
+                        .height(Length::Fixed(24.0))
+                        .into(),
+                    None => {
+                        iced::widget::Space::new(Length::Fixed(24.0), Length::Fixed(24.0)).into()
+                    }
+                };
+
+                let name = user.name.clone();
+
+                column.push(
+                    row![
+                        avatar,
+                        checkbox(&user.name, self.is_tracked(&user.name), move |val| {
+                            Message::ToggleUsername(name.clone(), val)
+                        }),
+                    ]
+                    .spacing(6)
+                    .align_items(Alignment::Center),
+                )
+            },
+        )
+    }
+
+    fn sessions_view(&self) -> iced::widget::Column<'_, Message> {
+        let filter = &self.gui_config.sessions;
+
+        let sessions = self.sessions.iter().fold(
+            column![text("Sessions:")]
+                .spacing(8)
+                .align_items(Alignment::Start),
+            |column: iced::widget::Column<'_, Message>, session| {
+                let rules = [
+                    SessionMatch::Client(session.client.clone()),
+                    SessionMatch::Device(session.device_name.clone()),
+                ]
+                .into_iter()
+                .fold(
+                    column![].spacing(3).align_items(Alignment::Start),
+                    |column: iced::widget::Column<'_, Message>, rule| {
+                        let pin = rule.clone();
+                        let exclude = rule.clone();
+
+                        column.push(
+                            row![
+                                text(rule.to_string()),
+                                checkbox("Pin", filter.pinned.contains(&rule), move |val| {
+                                    Message::PinSession(pin.clone(), val)
+                                }),
+                                checkbox("Exclude", filter.excluded.contains(&rule), move |val| {
+                                    Message::ExcludeSession(exclude.clone(), val)
+                                }),
+                            ]
+                            .spacing(6)
+                            .align_items(Alignment::Center),
+                        )
+                    },
+                );
+
+                let now_playing = match &session.now_playing {
+                    Some(title) => text(format!("Playing: {}", title)).size(12),
+                    None => text("Idle").size(12),
+                };
+
+                column.push(
+                    column![text(&session.user_name).size(20), rules, now_playing]
+                        .spacing(3)
+                        .align_items(Alignment::Start),
+                )
+            },
+        );
+
+        // Rules for devices that aren't online right now would be stuck otherwise
+        let rules = filter
+            .pinned
+            .iter()
+            .map(|rule| (rule, "Pinned"))
+            .chain(filter.excluded.iter().map(|rule| (rule, "Excluded")))
+            .fold(
+                column![text("Rules:")]
+                    .spacing(4)
+                    .align_items(Alignment::Start),
+                |column: iced::widget::Column<'_, Message>, (rule, kind)| {
+                    column.push(
+                        row![
+                            button("X").on_press(Message::RemoveSessionRule(rule.clone())),
+                            text(format!("{} {}", kind, rule)),
+                        ]
+                        .spacing(3)
+                        .align_items(Alignment::Center),
+                    )
+                },
+            );
+
+        column![text(self.sessions_error.clone()), sessions, rules]
+            .spacing(10)
+            .align_items(Alignment::Start)
+    }
+
+    fn backups_view(&self) -> iced::widget::Row<'_, Message> {
+        if self.backups.is_empty() {
+            return row![];
+        }
+
+        let restore = match self.selected_backup {
+            Some(_) => button("Restore backup").on_press(Message::RestoreBackup),
+            None => button("Restore backup"),
+        };
+
+        row![
+            pick_list(
+                &self.backups[..],
+                self.selected_backup.clone(),
+                Message::SelectBackup
+            )
+            .placeholder("Backups"),
+            restore.padding(5),
+        ]
+        .spacing(3)
+        .align_items(Alignment::Center)
+    }
+
+    fn now_playing_view(&self) -> iced::widget::Column<'_, Message> {
+        let now_playing = match &self.now_playing {
+            Some(now_playing) => now_playing,
+            None => return column![],
+        };
+
+        let mut elapsed = now_playing.position;
+        if !now_playing.paused {
+            elapsed += self.now_playing_updated.elapsed().as_secs();
+        }
+
+        let cover = match &self.cover {
+            Some((item_id, cover)) if item_id == &now_playing.item_id => {
+                column![image(cover.clone()).height(Length::Fixed(120.0))]
+            }
+            _ => column![],
+        };
+
+        let title = text(format!("{} {}", now_playing.icon(), now_playing.title)).size(20);
+
+        let progress = match now_playing.runtime {
+            Some(runtime) => {
+                let elapsed = elapsed.min(runtime);
+                column![
+                    progress_bar(0.0..=runtime as f32, elapsed as f32).height(Length::Fixed(6.0)),
+                    row![
+                        text(now_playing::format_time(elapsed)).width(Length::Fill),
+                        text(format!("-{}", now_playing::format_time(runtime - elapsed))),
+                    ],
+                ]
+                .spacing(3)
+            }
+            None => column![text(now_playing::format_time(elapsed))],
+        };
+
+        let paused = match now_playing.paused {
+            true => text("Paused"),
+            false => text(""),
+        };
+
+        column![
+            cover,
+            title,
+            text(now_playing.subtitle.clone()),
+            progress,
+            paused
+        ]
+        .spacing(3)
+        .align_items(Alignment::Center)
+    }
+
+    /// Brings the settings state in line with `self.config` and refetches the libraries.
+    fn apply_config(&mut self) -> Command<Message> {
+        self.gui_config = GuiConfig::load(&self.config_path);
+        self.theme = self.gui_config.theme();
+
+        let usernames = self.usernames();
+        self.gui_config.users = gui_config::reconcile_users(&self.gui_config.users, &usernames);
+
+        let libraries = self
+            .config
+            .jellyfin
+            .blacklist
+            .clone()
+            .and_then(|blacklist| blacklist.libraries)
+            .unwrap_or_default();
+        self.gui_config.blacklisted_libraries = gui_config::reconcile_libraries(
+            &self.gui_config.blacklisted_libraries,
+            &libraries,
+            &[],
+        );
+
+        self.whitelist_media_types.update(&self.config);
+
+        self.custom_buttons = self.buttons.one.name != "dynamic"
+            || self.buttons.one.url != "dynamic"
+            || self.buttons.two.name != "dynamic"
+            || self.buttons.two.url != "dynamic";
+
+        let default_button = Button {
+            name: String::from("dynamic"),
+            url: String::from("dynamic"),
+        };
+
+        let buttons = self
+            .config
+            .discord
+            .clone()
+            .and_then(|discord| discord.buttons)
+            .unwrap_or(vec![default_button.clone(), default_button]);
+
+        self.buttons.update(buttons);
+
+        self.image_options.enabled = self
+            .config
+            .images
+            .clone()
+            .is_some_and(|images| images.enable_images.is_some_and(|enabled| enabled));
+
+        if self
+            .config
+            .images
+            .clone()
+            .is_some_and(|images| images.imgur_images.is_some_and(|imgur| imgur))
+        {
+            self.image_options.imgur = true;
+
+            self.image_options.imgur_client_id = self
+                .config
+                .imgur
+                .clone()
+                .and_then(|imgur| imgur.client_id)
+                .unwrap_or_default();
+        } else {
+            self.image_options.imgur = false;
+        }
+
+        self.music_display.update(&self.config);
+
+        self.application_id = self
+            .config
+            .discord
+            .clone()
+            .and_then(|discord| discord.application_id)
+            .unwrap_or("1053747938519679018".to_string());
+
+        self.saved = self.snapshot();
+
+        Command::perform(
+            libraries::get_libraries(
+                self.config.jellyfin.url.clone(),
+                self.config.jellyfin.api_key.clone(),
+                self.usernames(),
+            ),
+            Message::UpdateLibraries,
+        )
+    }
+
+    /// The tracked users jellyfin-rpc gets to see, in order of priority.
+    fn usernames(&self) -> Vec<String> {
+        match &self.config.jellyfin.username {
+            Username::Vec(usernames) => usernames.to_vec(),
+            Username::String(username) => vec![username.to_string()],
+        }
+    }
+
+    fn is_tracked(&self, name: &str) -> bool {
+        self.gui_config.users.iter().any(|user| user.name == name)
+    }
+
+    /// Only the enabled users end up in `jellyfin.username`, in order of priority.
+    fn set_users(&mut self, users: Vec<TrackedUser>) {
+        self.config.jellyfin.username = Username::Vec(
+            users
+                .iter()
+                .filter(|user| user.enabled)
+                .map(|user| user.name.clone())
+                .collect(),
+        );
+        self.gui_config.users = users;
+    }
+
+    /// The config as it would be saved, with everything from the settings panels applied.
+    fn pending_config(&self) -> Config {
+        let mut config = self.config.clone();
+
+        if let Some(mut discord) = config.discord.clone() {
+            discord.buttons = Some(vec![self.buttons.one.clone(), self.buttons.two.clone()]);
+            discord.application_id = Some(self.application_id.clone());
+            config.discord = Some(discord);
+        } else {
+            config.discord = Some(Discord {
+                application_id: None,
+                buttons: Some(vec![self.buttons.one.clone(), self.buttons.two.clone()]),
+            })
+        }
+
+        config.images = Some(Images {
+            enable_images: Some(self.image_options.enabled),
+            imgur_images: Some(self.image_options.imgur),
+        });
+
+        config.imgur = Some(Imgur {
+            client_id: Some(self.image_options.imgur_client_id.clone()),
+        });
+
+        config.jellyfin.music = Some(Music {
+            display: Some(Display::Vec(self.music_display.display())),
+            separator: Some(self.music_display.separator),
+        });
+
+        config.jellyfin.blacklist = Some(Blacklist {
+            media_types: config
+                .jellyfin
+                .blacklist
+                .clone()
+                .and_then(|blacklist| blacklist.media_types),
+            libraries: Some(
+                self.gui_config
+                    .blacklisted_libraries
+                    .iter()
+                    .map(|library| library.name.to_owned())
+                    .collect(),
+            ),
+        });
+
+        config
+    }
+
+    /// What would end up in the file if we saved right now.
+    fn snapshot(&self) -> String {
+        gui_config::merge(&self.pending_config(), &self.gui_config).unwrap_or_default()
+    }
+
+    fn has_unsaved_changes(&self) -> bool {
+        self.snapshot() != self.saved
+    }
+
+    /// Goes wherever the user was headed when they got asked about their unsaved changes.
+    fn leave(&mut self, leaving: Leaving) -> Command<Message> {
+        match leaving {
+            Leaving::Panel(panel) => self.update(Message::Open(panel)),
+            Leaving::Quit => iced::window::close(),
+            Leaving::Reload => self.update(Message::ReloadConfig),
+            Leaving::Switch(config_path) => self.switch_config(config_path),
+        }
+    }
+
+    fn switch_config(&mut self, config_path: String) -> Command<Message> {
+        self.config_path = config_path.clone();
+        self.restart.send(Some(config_path)).ok();
+        self.update(Message::ReloadConfig)
+    }
+
+    /// Reloads `self.config` from disk. A broken file is reported and left alone,
+    /// the last config that loaded stays in use until it's fixed.
+    ///
+    /// Returns `None` when the file is broken, otherwise whatever it takes to get
+    /// the server going again if the file was broken before.
+    fn load_config(&mut self) -> Option<Command<Message>> {
+        match config_file::load(&self.config_path) {
+            Ok(config) => {
+                self.config = config;
+
+                if self.config_error.take().is_none() {
+                    return Some(Command::none());
+                }
+                self.error = "None".to_string();
+
+                // The broken file either kept the server from starting or made it give up
+                Some(match self.server {
+                    Some(_) if matches!(self.panel, Panel::Setup(_)) => Command::none(),
+                    Some(_) => self.start_server(),
+                    None => {
+                        self.restart.send(None).ok();
+                        Command::none()
+                    }
+                })
+            }
+            Err(err) => {
+                self.error = format!("{}: {}", server::CONFIG_ERROR, err);
+                self.config_error = Some(err);
+                None
+            }
+        }
+    }
+
+    /// Starts the presence loop if it's still waiting on the initial setup.
+    fn start_server(&mut self) -> Command<Message> {
+        match self.server.take() {
+            Some(channels) => Command::perform(
+                server::run(
+                    self.config_path.clone(),
+                    self.config.clone(),
+                    self.image_urls.clone(),
+                    channels,
+                ),
+                |_| Message::Open(Panel::Main),
+            ),
+            None => Command::none(),
+        }
+    }
 }
 
 impl Application for Gui {
     type Executor = executor::Default;
-    type Flags = ();
+    type Flags = (Args, ipc::Listener);
     type Message = Message;
     type Theme = Theme;
 
-    fn new(_flags: Self::Flags) -> (Gui, Command<Message>) {
-        let args = Args::parse();
-
-        let config_path = match args.config.clone() {
-            Some(path) => path,
-            None => get_config_path().unwrap_or_else(|err| {
-                eprintln!("Error determining config path: {:?}", err);
-                std::process::exit(1)
-            }),
-        };
-
-        std::fs::create_dir_all(
-            std::path::Path::new(&config_path)
-                .parent()
-                .expect("Invalid config file path"),
-        )
-        .ok();
+    fn new((args, listener): Self::Flags) -> (Gui, Command<Message>) {
+        let config_path = crate::config_path(&args);
 
         let (tx_server, rx_iced) = mpsc::channel();
         let (tx_iced, rx_server) = mpsc::channel();
+        let (restart_iced, restart_server) = tokio::sync::mpsc::unbounded_channel();
+
+        let needs_setup = !std::path::Path::new(&config_path).exists();
+
+        let (config, config_error) = match config_file::load(&config_path) {
+            Ok(config) => (config, None),
+            Err(err) => (Config::default(), Some(err)),
+        };
 
-        let config = Config::load(&config_path).unwrap_or_else(|_| Config::default());
+        // A broken config needs repairing, running the setup would overwrite it
+        let needs_setup =
+            config_error.is_none() && (needs_setup || config.jellyfin.api_key.is_empty());
+
+        let gui_config = GuiConfig::load(&config_path);
 
         let custom_buttons = false;
 
@@ -167,45 +872,93 @@ impl Application for Gui {
             .and_then(|discord| discord.buttons)
             .unwrap_or(vec![default_button.clone(), default_button]);
 
-        (
-            Gui {
-                status: "Not running".to_string(),
-                error: "None".to_string(),
-                config: config.clone(),
-                panel: Panel::Main,
-                whitelist_media_types: WhitelistMediaTypes::default(),
-                new_username: "".to_string(),
-                rx: rx_iced,
-                tx: tx_iced,
-                custom_buttons,
-                buttons: Buttons {
-                    one: buttons[0].clone(),
-                    two: buttons[1].clone(),
-                },
-                image_options: ImageOptions {
-                    enabled: false,
-                    imgur: false,
-                    imgur_client_id: "".to_string(),
-                },
-                libraries: Vec::new(),
-                config_path: config_path.clone(),
-                application_id: config
-                    .discord
-                    .clone()
-                    .and_then(|discord| discord.application_id)
-                    .unwrap_or("1053747938519679018".to_string()),
+        let application_id = config
+            .discord
+            .clone()
+            .and_then(|discord| discord.application_id)
+            .unwrap_or("1053747938519679018".to_string());
+
+        let mut gui = Gui {
+            status: "Not running".to_string(),
+            error: "None".to_string(),
+            config,
+            panel: Panel::Main,
+            whitelist_media_types: WhitelistMediaTypes::default(),
+            music_display: MusicDisplay::default(),
+            new_username: "".to_string(),
+            rx: Arc::new(Mutex::new(rx_iced)),
+            tx: tx_iced,
+            custom_buttons,
+            buttons: Buttons {
+                one: buttons[0].clone(),
+                two: buttons[1].clone(),
             },
-            Command::perform(
-                server::run(
-                    config_path,
-                    config,
-                    args.image_urls.clone(),
-                    tx_server,
-                    rx_server,
-                ),
-                |_| Message::Open(Panel::Main),
-            ),
-        )
+            image_options: ImageOptions {
+                enabled: false,
+                imgur: false,
+                imgur_client_id: "".to_string(),
+            },
+            libraries: Vec::new(),
+            config_path,
+            application_id,
+            setup: Setup::default(),
+            quick_connect: QuickConnect::default(),
+            servers: Vec::new(),
+            discovering: false,
+            server: Some(server::Channels {
+                tx: tx_server,
+                rx: rx_server,
+                restart: restart_server,
+            }),
+            restart: restart_iced,
+            crashes: 0,
+            image_urls: args.image_urls,
+            tray: Tray::new(),
+            visible: true,
+            theme: gui_config.theme(),
+            gui_config,
+            now_playing: None,
+            now_playing_updated: std::time::Instant::now(),
+            cover: None,
+            log: Log::default(),
+            log_level: Level::Info,
+            log_filter: String::new(),
+            log_export: String::new(),
+            discord_connected: false,
+            ipc_status: Arc::new(Mutex::new(ipc::Status::default())),
+            listener: Arc::new(Mutex::new(Some(listener))),
+            notice: String::new(),
+            config_error,
+            backups: Vec::new(),
+            selected_backup: None,
+            saved: String::new(),
+            leaving: None,
+            connection_test: None,
+            testing_connection: false,
+            server_users: Vec::new(),
+            server_users_error: String::new(),
+            avatars: std::collections::HashMap::new(),
+            sessions: Vec::new(),
+            sessions_error: String::new(),
+            libraries_error: String::new(),
+        };
+
+        gui.saved = gui.snapshot();
+
+        if needs_setup {
+            gui.panel = Panel::Setup(Step::Server);
+            return (gui, Command::none());
+        }
+
+        // The server only gets started once the file is fixed
+        if let Some(err) = &gui.config_error {
+            gui.error = format!("{}: {}", server::CONFIG_ERROR, err);
+            return (gui, Command::none());
+        }
+
+        let command = gui.start_server();
+
+        (gui, command)
     }
 
     fn title(&self) -> String {
@@ -215,15 +968,17 @@ impl Application for Gui {
     fn update(&mut self, message: Message) -> Command<Message> {
         match message {
             Message::ReloadConfig => {
-                match Config::load(&self.config_path) {
-                    Ok(config) => {
-                        self.config = config;
-                    }
-                    Err(_) => self.config = Config::default(),
+                if self.has_unsaved_changes() {
+                    self.leaving = Some(Leaving::Reload);
+                    return Command::none();
                 }
-                self.whitelist_media_types.update(&self.config);
+
+                let started = match self.load_config() {
+                    Some(started) => started,
+                    None => return Command::none(),
+                };
                 let _ = self.tx.send(RpcCommand::ReloadConfig);
-                Command::none()
+                Command::batch([started, self.apply_config()])
             }
             Message::Start => match self.tx.send(RpcCommand::Start) {
                 Ok(()) => Command::none(),
@@ -233,87 +988,171 @@ impl Application for Gui {
                 Ok(()) => Command::none(),
                 Err(_) => Command::none(),
             },
-            Message::Update => {
-                match self.rx.try_recv() {
-                    Ok(Event::Information(status, _)) => self.status = status,
-                    Ok(Event::Error(data, error)) => self.error = error,
-                    Ok(_) => (),
-                    Err(_) => (),
+            Message::ConfigChanged => {
+                // Don't throw away what's being edited, saving will replace the file anyway
+                if self.has_unsaved_changes() {
+                    self.notice =
+                        "Config changed on disk, save or discard your changes".to_string();
+                    return Command::none();
                 }
-                Command::none()
-            }
-            Message::Open(panel) => {
-                self.panel = panel;
 
-                if self.panel == Panel::Main {
-                    match Config::load(&self.config_path) {
-                        Ok(config) => {
-                            self.config = config;
-                        }
-                        Err(_) => self.config = Config::default(),
-                    };
+                let previous = serde_json::to_value(&self.config).ok();
+                let was_invalid = self.config_error.is_some();
 
-                    self.whitelist_media_types.update(&self.config);
-
-                    self.custom_buttons = self.buttons.one.name != "dynamic"
-                        || self.buttons.one.url != "dynamic"
-                        || self.buttons.two.name != "dynamic"
-                        || self.buttons.two.url != "dynamic";
-
-                    let default_button = Button {
-                        name: String::from("dynamic"),
-                        url: String::from("dynamic"),
-                    };
+                let started = match self.load_config() {
+                    Some(started) => started,
+                    None => return Command::none(),
+                };
 
-                    let buttons = self
-                        .config
-                        .discord
-                        .clone()
-                        .and_then(|discord| discord.buttons)
-                        .unwrap_or(vec![default_button.clone(), default_button]);
+                // Our own saves end up here too, nothing to do if nothing changed
+                if !was_invalid
+                    && serde_json::to_value(&self.config).ok() == previous
+                    && GuiConfig::load(&self.config_path) == self.gui_config
+                {
+                    return Command::none();
+                }
 
-                    self.buttons.update(buttons);
+                if self.panel == Panel::Repair {
+                    self.panel = Panel::Main;
+                }
 
-                    self.image_options.enabled =
-                        self.config.images.clone().is_some_and(|images| {
-                            images.enable_images.is_some_and(|enabled| enabled)
-                        });
+                self.tx.send(RpcCommand::ReloadConfig).ok();
+                self.notice = "Config reloaded from disk".to_string();
+                Command::batch([started, self.apply_config()])
+            }
+            Message::Restart => {
+                self.crashes = 0;
+                self.restart.send(None).ok();
+                Command::none()
+            }
+            Message::Ipc(request) => match request {
+                Request::Start => self.update(Message::Start),
+                Request::Stop => self.update(Message::Stop),
+                // The window might be hidden, so refuse like ConfigChanged instead of prompting
+                Request::ReloadConfig if self.has_unsaved_changes() => {
+                    self.notice =
+                        "Reload requested, save or discard your changes first".to_string();
+                    Command::none()
+                }
+                Request::ReloadConfig => self.update(Message::ReloadConfig),
+                Request::Restart => self.update(Message::Restart),
+                // Answered by the listener itself
+                Request::Status => Command::none(),
+                Request::Show { config } => {
+                    self.visible = true;
+
+                    let mut commands = vec![
+                        iced::window::change_mode(iced::window::Mode::Windowed),
+                        iced::window::gain_focus(),
+                    ];
 
-                    if self
-                        .config
-                        .images
-                        .clone()
-                        .is_some_and(|images| images.imgur_images.is_some_and(|imgur| imgur))
+                    // Another launch asked for a different config, so we switch over to it
+                    if let Some(config_path) = config
+                        .map(|path| crate::canonical_path(&path))
+                        .filter(|path| path != &self.config_path)
                     {
-                        self.image_options.imgur = true;
+                        match self.has_unsaved_changes() {
+                            true => self.leaving = Some(Leaving::Switch(config_path)),
+                            false => commands.push(self.switch_config(config_path)),
+                        }
+                    }
+
+                    Command::batch(commands)
+                }
+            },
+            Message::Event(entry) => {
+                self.log.push(entry.clone());
+                self.ipc_status.lock().unwrap().update(&entry);
+
+                if entry.title == server::DISCORD_CONNECTED {
+                    self.discord_connected = true;
+                    return Command::none();
+                } else if entry.title == server::DISCORD_DISCONNECTED {
+                    self.discord_connected = false;
+                    self.error = entry.message;
+                    return Command::none();
+                } else if entry.title == server::SERVER_CRASHED {
+                    // The server starts counting over once a run was stable for a while
+                    self.crashes = server::crash_count(&entry.message).unwrap_or(self.crashes + 1);
+                }
 
-                        self.image_options.imgur_client_id = self
-                            .config
-                            .imgur
-                            .clone()
-                            .and_then(|imgur| imgur.client_id)
-                            .unwrap_or_default();
-                    } else {
-                        self.image_options.imgur = false;
+                match entry.level {
+                    Level::Info => {
+                        if let Some(tray) = &self.tray {
+                            tray.set_status(&entry.title);
+                        }
+                        self.status = entry.title;
+
+                        return Command::perform(
+                            now_playing::get_now_playing(
+                                self.config.jellyfin.url.clone(),
+                                self.config.jellyfin.api_key.clone(),
+                                self.usernames(),
+                                self.gui_config.sessions.clone(),
+                            ),
+                            Message::UpdateNowPlaying,
+                        );
+                    }
+                    // The broken config file is what needs fixing first
+                    Level::Error if self.config_error.is_some() => (),
+                    Level::Error => {
+                        self.error = match entry.message.is_empty() {
+                            true => entry.title,
+                            false => entry.message,
+                        }
                     }
+                    Level::Debug => (),
+                }
+                Command::none()
+            }
+            // Only here to redraw the progress of whatever is playing
+            Message::Tick => Command::none(),
+            Message::Open(panel) => {
+                if matches!(self.panel, Panel::Settings(_))
+                    && !matches!(panel, Panel::Settings(_))
+                    && self.has_unsaved_changes()
+                {
+                    self.leaving = Some(Leaving::Panel(panel));
+                    return Command::none();
+                }
+
+                self.panel = panel;
+
+                if matches!(self.panel, Panel::Settings(Setting::Main) | Panel::Repair) {
+                    self.backups = config_file::backups(&self.config_path);
+                }
+
+                if self.panel == Panel::Settings(Setting::Sessions) {
+                    return self.update(Message::RefreshSessions);
+                }
 
+                if self.panel == Panel::Settings(Setting::Users) {
                     return Command::perform(
-                        get_libraries(
+                        users::get_users(
                             self.config.jellyfin.url.clone(),
                             self.config.jellyfin.api_key.clone(),
                         ),
-                        |libraries| Message::UpdateLibraries(libraries.unwrap()),
+                        Message::ServerUsers,
                     );
                 }
 
+                if self.panel == Panel::Main {
+                    return match self.load_config() {
+                        Some(started) => Command::batch([started, self.apply_config()]),
+                        None => Command::none(),
+                    };
+                }
+
                 Command::none()
             }
             Message::UpdateUrl(url) => {
                 self.config.jellyfin.url = url;
+                self.connection_test = None;
                 Command::none()
             }
             Message::UpdateApiKey(api_key) => {
                 self.config.jellyfin.api_key = api_key;
+                self.connection_test = None;
                 Command::none()
             }
             Message::UpdateApplicationId(application_id) => {
@@ -351,29 +1190,151 @@ impl Application for Gui {
                 Command::none()
             }
             Message::AddUsername => {
-                let mut usernames = match &self.config.jellyfin.username {
-                    Username::Vec(usernames) => usernames.to_vec(),
-                    Username::String(username) => vec![username.to_string()],
-                };
+                let mut users = self.gui_config.users.clone();
 
-                if !usernames.contains(&self.new_username) {
-                    usernames.push(self.new_username.clone());
+                if !self.is_tracked(&self.new_username) {
+                    users.push(TrackedUser {
+                        name: self.new_username.clone(),
+                        enabled: true,
+                    });
                 }
 
                 self.new_username = "".to_string();
-                self.config.jellyfin.username = Username::Vec(usernames);
+                self.set_users(users);
+
+                Command::none()
+            }
+            Message::ToggleUsername(name, val) => {
+                let mut users = self.gui_config.users.clone();
+
+                if !val {
+                    users.retain(|user| user.name != name);
+                } else if !self.is_tracked(&name) {
+                    users.push(TrackedUser {
+                        name,
+                        enabled: true,
+                    });
+                }
+
+                self.set_users(users);
+
+                Command::none()
+            }
+            Message::ToggleUserEnabled(name, val) => {
+                let mut users = self.gui_config.users.clone();
+
+                for user in &mut users {
+                    if user.name == name {
+                        user.enabled = val;
+                    }
+                }
+
+                self.set_users(users);
+
+                Command::none()
+            }
+            Message::MoveUsernameUp(name) => {
+                let mut users = self.gui_config.users.clone();
+
+                if let Some(index) = users.iter().position(|user| user.name == name) {
+                    if index > 0 {
+                        users.swap(index, index - 1);
+                    }
+                }
+
+                self.set_users(users);
 
                 Command::none()
             }
+            Message::MoveUsernameDown(name) => {
+                let mut users = self.gui_config.users.clone();
+
+                if let Some(index) = users.iter().position(|user| user.name == name) {
+                    if index + 1 < users.len() {
+                        users.swap(index, index + 1);
+                    }
+                }
+
+                self.set_users(users);
+
+                Command::none()
+            }
+            Message::ServerUsers(result) => match result {
+                Ok(server_users) => {
+                    self.server_users_error = String::new();
+
+                    let avatars = server_users
+                        .iter()
+                        .filter(|user| {
+                            user.primary_image_tag.is_some() && !self.avatars.contains_key(&user.id)
+                        })
+                        .map(|user| {
+                            let id = user.id.clone();
+                            Command::perform(
+                                users::get_avatar(
+                                    self.config.jellyfin.url.clone(),
+                                    self.config.jellyfin.api_key.clone(),
+                                    user.id.clone(),
+                                ),
+                                move |avatar| Message::UpdateAvatar(id.clone(), avatar),
+                            )
+                        })
+                        .collect::<Vec<_>>();
+
+                    self.server_users = server_users;
+
+                    Command::batch(avatars)
+                }
+                Err(err) => {
+                    self.server_users = Vec::new();
+                    self.server_users_error = err;
+                    Command::none()
+                }
+            },
+            Message::UpdateAvatar(user_id, avatar) => {
+                // No avatar is fine, the user just gets listed without one
+                if let Ok(bytes) = avatar {
+                    self.avatars
+                        .insert(user_id, image::Handle::from_memory(bytes));
+                }
+                Command::none()
+            }
+            Message::RefreshSessions => Command::perform(
+                now_playing::get_sessions(
+                    self.config.jellyfin.url.clone(),
+                    self.config.jellyfin.api_key.clone(),
+                    self.usernames(),
+                ),
+                Message::Sessions,
+            ),
+            Message::Sessions(result) => {
+                match result {
+                    Ok(sessions) => {
+                        self.sessions = sessions;
+                        self.sessions_error = String::new();
+                    }
+                    Err(err) => self.sessions_error = err,
+                }
+                Command::none()
+            }
+            Message::PinSession(rule, val) => {
+                self.gui_config.sessions.pin(rule, val);
+                Command::none()
+            }
+            Message::ExcludeSession(rule, val) => {
+                self.gui_config.sessions.exclude(rule, val);
+                Command::none()
+            }
+            Message::RemoveSessionRule(rule) => {
+                self.gui_config.sessions.remove(&rule);
+                Command::none()
+            }
             Message::RemoveUsername(pattern) => {
-                let mut usernames = match &self.config.jellyfin.username {
-                    Username::Vec(usernames) => usernames.to_vec(),
-                    Username::String(username) => vec![username.to_string()],
-                };
+                let mut users = self.gui_config.users.clone();
 
-                usernames.retain(|username| username != &pattern);
+                users.retain(|user| user.name != pattern);
 
-                self.config.jellyfin.username = Username::Vec(usernames);
+                self.set_users(users);
 
                 Command::none()
             }
@@ -390,109 +1351,632 @@ impl Application for Gui {
                 Command::none()
             }
             Message::UpdateLibraries(libraries) => {
-                self.libraries = libraries
+                let libraries = match libraries {
+                    Ok(libraries) => libraries,
+                    Err(err) => {
+                        self.libraries = Vec::new();
+                        self.libraries_error = err;
+                        return Command::none();
+                    }
+                };
+
+                self.libraries_error = String::new();
+
+                let was_saved = !self.has_unsaved_changes();
+
+                let blacklisted = &self.gui_config.blacklisted_libraries;
+                let names: Vec<String> = blacklisted
+                    .iter()
+                    .map(|library| library.name.clone())
+                    .collect();
+                let ids: Vec<(String, String)> = libraries
                     .iter()
+                    .map(|library| (library.id.clone(), library.name.clone()))
+                    .collect();
+
+                let reconciled = gui_config::reconcile_libraries(blacklisted, &names, &ids);
+                let changed = &reconciled != blacklisted;
+                self.gui_config.blacklisted_libraries = reconciled;
+
+                self.libraries = libraries
+                    .into_iter()
                     .map(|library| Library {
-                        name: library.to_string(),
-                        enabled: true,
+                        enabled: !self
+                            .gui_config
+                            .blacklisted_libraries
+                            .iter()
+                            .any(|blacklisted| blacklisted.id == library.id),
+                        id: library.id,
+                        name: library.name,
+                        collection_type: library.collection_type,
+                        item_count: library.item_count,
                     })
                     .collect();
 
-                if let Some(blacklist) = self.config.jellyfin.blacklist.clone() {
-                    if let Some(libraries) = blacklist.libraries {
-                        for library in &mut self.libraries {
-                            if libraries.contains(&library.name) {
-                                library.enabled = false;
-                            }
+                // Picking up IDs and renames isn't something the user did, so it
+                // doesn't count as an unsaved change and goes straight to the file,
+                // jellyfin-rpc only knows the libraries by their new names
+                if was_saved && changed && self.config_error.is_none() {
+                    self.config = self.pending_config();
+
+                    match write_config(&self.config_path, &self.config, &self.gui_config) {
+                        Ok(()) => {
+                            self.saved = self.snapshot();
+                            self.tx.send(RpcCommand::ReloadConfig).ok();
                         }
+                        Err(err) => self.error = format!("{:?}", err),
                     }
+                } else if was_saved {
+                    self.saved = self.snapshot();
                 }
 
                 Command::none()
             }
             Message::ToggleLibrary(library, val) => {
+                self.gui_config
+                    .blacklisted_libraries
+                    .retain(|blacklisted| blacklisted.id != library.id);
+
+                if !val {
+                    self.gui_config
+                        .blacklisted_libraries
+                        .push(BlacklistedLibrary {
+                            id: library.id.clone(),
+                            name: library.name.clone(),
+                        });
+                }
+
                 for _library in &mut self.libraries {
-                    if library == *_library {
+                    if _library.id == library.id {
                         _library.enabled = val;
                     }
                 }
                 Command::none()
             }
-            Message::SaveSettings => {
-                if let Some(mut discord) = self.config.discord.clone() {
-                    discord.buttons =
-                        Some(vec![self.buttons.one.clone(), self.buttons.two.clone()]);
-                    discord.application_id = Some(self.application_id.clone());
-                    self.config.discord = Some(discord);
-                } else {
-                    self.config.discord = Some(Discord {
-                        application_id: None,
-                        buttons: Some(vec![self.buttons.one.clone(), self.buttons.two.clone()]),
-                    })
+            Message::RemoveStaleLibrary(name) => {
+                self.gui_config
+                    .blacklisted_libraries
+                    .retain(|library| library.name != name);
+                Command::none()
+            }
+            Message::SaveAndLeave => {
+                let save = self.update(Message::SaveSettings);
+
+                // Saving didn't work out, so the prompt stays up along with the error
+                if self.has_unsaved_changes() {
+                    return save;
                 }
 
-                self.config.images = Some(Images {
-                    enable_images: Some(self.image_options.enabled),
-                    imgur_images: Some(self.image_options.imgur),
-                });
-
-                self.config.imgur = Some(Imgur {
-                    client_id: Some(self.image_options.imgur_client_id.clone()),
-                });
-
-                match self.config.jellyfin.blacklist.clone() {
-                    Some(_) => {
-                        self.config.jellyfin.blacklist = Some(Blacklist {
-                            media_types: self
-                                .config
-                                .jellyfin
-                                .blacklist
-                                .clone()
-                                .unwrap()
-                                .media_types,
-                            libraries: Some(
-                                self.libraries
-                                    .iter()
-                                    .filter(|library| !library.enabled)
-                                    .map(|library| library.name.to_owned())
-                                    .collect(),
-                            ),
-                        })
-                    }
+                match self.leaving.take() {
+                    Some(leaving) => Command::batch([save, self.leave(leaving)]),
+                    None => save,
+                }
+            }
+            Message::DiscardChanges => {
+                let discard = match self.load_config() {
+                    Some(started) => Command::batch([started, self.apply_config()]),
+                    // Nothing to go back to while the file is broken, so just stop asking
                     None => {
-                        self.config.jellyfin.blacklist = Some(Blacklist {
-                            media_types: None,
-                            libraries: Some(
-                                self.libraries
-                                    .iter()
-                                    .filter(|library| !library.enabled)
-                                    .map(|library| library.name.to_owned())
-                                    .collect(),
-                            ),
-                        })
+                        self.saved = self.snapshot();
+                        Command::none()
                     }
                 };
 
-                match std::fs::write(
-                    &self.config_path,
-                    serde_json::to_string_pretty(&self.config).unwrap(),
-                ) {
+                match self.leaving.take() {
+                    Some(leaving) => Command::batch([discard, self.leave(leaving)]),
+                    None => discard,
+                }
+            }
+            Message::CancelLeave => {
+                self.leaving = None;
+                Command::none()
+            }
+            Message::TestConnection => {
+                self.testing_connection = true;
+                self.connection_test = None;
+                Command::perform(
+                    connection::test(
+                        self.config.jellyfin.url.clone(),
+                        self.config.jellyfin.api_key.clone(),
+                        self.usernames(),
+                    ),
+                    Message::ConnectionTested,
+                )
+            }
+            Message::ConnectionTested(result) => {
+                self.testing_connection = false;
+                self.connection_test = Some(result);
+                Command::none()
+            }
+            Message::SelectBackup(backup) => {
+                self.selected_backup = Some(backup);
+                Command::none()
+            }
+            Message::RestoreBackup => {
+                let backup = match self.selected_backup.take() {
+                    Some(backup) => backup,
+                    None => return Command::none(),
+                };
+
+                if let Err(err) = config_file::restore(&self.config_path, &backup) {
+                    self.error = format!("Couldn't restore the backup: {:?}", err);
+                    return Command::none();
+                }
+
+                self.backups = config_file::backups(&self.config_path);
+
+                let started = match self.load_config() {
+                    Some(started) => started,
+                    None => return Command::none(),
+                };
+
+                if self.panel == Panel::Repair {
+                    self.panel = Panel::Main;
+                }
+
+                self.tx.send(RpcCommand::ReloadConfig).ok();
+                self.notice = format!("Restored the backup from {}", backup);
+                Command::batch([started, self.apply_config()])
+            }
+            Message::SaveSettings => {
+                if let Some(err) = &self.config_error {
+                    self.error = format!("Fix the config file before saving: {}", err);
+                    return Command::none();
+                }
+
+                self.config = self.pending_config();
+
+                match write_config(&self.config_path, &self.config, &self.gui_config) {
                     Ok(()) => {
+                        self.saved = self.snapshot();
                         self.tx.send(RpcCommand::ReloadConfig).ok();
+                        return self.start_server();
                     }
                     Err(err) => self.error = format!("{:?}", err),
                 }
                 Command::none()
             }
+            Message::SetupCheckServer => {
+                self.setup.busy = true;
+                self.setup.error = "".to_string();
+                Command::perform(
+                    setup::get_server_name(self.config.jellyfin.url.clone()),
+                    Message::SetupServerChecked,
+                )
+            }
+            Message::SetupServerChecked(result) => {
+                self.setup.busy = false;
+                match result {
+                    Ok(server_name) => {
+                        self.setup.server_name = server_name;
+                        self.panel = Panel::Setup(Step::Credentials);
+                    }
+                    Err(err) => self.setup.error = err,
+                }
+                Command::none()
+            }
+            Message::SetupUsername(username) => {
+                self.setup.username = username;
+                Command::none()
+            }
+            Message::SetupPassword(password) => {
+                self.setup.password = password;
+                Command::none()
+            }
+            Message::SetupLogin => {
+                self.setup.busy = true;
+                self.setup.error = "".to_string();
+                Command::perform(
+                    setup::authenticate(
+                        self.config.jellyfin.url.clone(),
+                        self.setup.username.clone(),
+                        self.setup.password.clone(),
+                    ),
+                    Message::SetupAuthenticated,
+                )
+            }
+            Message::SetupAuthenticated(result) => match result {
+                Ok(authentication) => {
+                    self.setup.password = "".to_string();
+                    self.setup.access_token = authentication.access_token.clone();
+                    self.setup.users = vec![authentication.username.clone()];
+                    self.setup.selected_users = vec![authentication.username];
+                    self.panel = Panel::Setup(Step::Users);
+
+                    Command::perform(
+                        setup::get_users(
+                            self.config.jellyfin.url.clone(),
+                            authentication.access_token,
+                        ),
+                        Message::SetupUsers,
+                    )
+                }
+                Err(err) => {
+                    self.setup.busy = false;
+                    self.setup.error = err;
+                    Command::none()
+                }
+            },
+            Message::SetupUsers(result) => {
+                self.setup.busy = false;
+                // Non-admin accounts can't list users, so we just keep the one we logged in as
+                if let Ok(users) = result {
+                    self.setup.users = users;
+                }
+                Command::none()
+            }
+            Message::SetupToggleUser(username, val) => {
+                if val {
+                    if !self.setup.selected_users.contains(&username) {
+                        self.setup.selected_users.push(username);
+                    }
+                } else {
+                    self.setup.selected_users.retain(|user| user != &username);
+                }
+                Command::none()
+            }
+            Message::SetupFinish => {
+                if let Some(err) = &self.config_error {
+                    self.setup.error = format!("{}: {}", server::CONFIG_ERROR, err);
+                    return Command::none();
+                }
+
+                self.config.jellyfin.api_key = self.setup.access_token.clone();
+                self.config.jellyfin.username = Username::Vec(self.setup.selected_users.clone());
+
+                match self.config.discord.as_mut() {
+                    Some(discord) => discord.application_id = Some(self.application_id.clone()),
+                    None => {
+                        self.config.discord = Some(Discord {
+                            application_id: Some(self.application_id.clone()),
+                            buttons: None,
+                        })
+                    }
+                }
+
+                match write_config(&self.config_path, &self.config, &self.gui_config) {
+                    Ok(()) => {
+                        self.setup = Setup::default();
+                        self.quick_connect = QuickConnect::default();
+                        self.panel = Panel::Main;
+                        self.start_server()
+                    }
+                    Err(err) => {
+                        self.setup.error = format!("{:?}", err);
+                        Command::none()
+                    }
+                }
+            }
+            Message::QuickConnectStart => {
+                if let Panel::Settings(_) = self.panel {
+                    self.panel = Panel::Settings(Setting::QuickConnect);
+                }
+
+                self.quick_connect = QuickConnect::default();
+                Command::perform(
+                    setup::initiate_quick_connect(self.config.jellyfin.url.clone()),
+                    Message::QuickConnectInitiated,
+                )
+            }
+            Message::QuickConnectInitiated(result) => match result {
+                Ok(code) => {
+                    self.quick_connect.secret = code.secret.clone();
+                    self.quick_connect.code = code.code;
+
+                    Command::perform(
+                        setup::poll_quick_connect(
+                            self.config.jellyfin.url.clone(),
+                            code.secret.clone(),
+                        ),
+                        move |result| Message::QuickConnectPolled(code.secret.clone(), result),
+                    )
+                }
+                Err(err) => {
+                    self.quick_connect.error = err;
+                    Command::none()
+                }
+            },
+            Message::QuickConnectPolled(secret, result) => {
+                // The code was cancelled or replaced while we were waiting
+                if secret != self.quick_connect.secret {
+                    return Command::none();
+                }
+
+                match result {
+                    Ok(true) => Command::perform(
+                        setup::authenticate_quick_connect(self.config.jellyfin.url.clone(), secret),
+                        Message::QuickConnectAuthenticated,
+                    ),
+                    Ok(false) => Command::perform(
+                        setup::poll_quick_connect(self.config.jellyfin.url.clone(), secret.clone()),
+                        move |result| Message::QuickConnectPolled(secret.clone(), result),
+                    ),
+                    Err(err) => {
+                        self.quick_connect = QuickConnect {
+                            error: err,
+                            ..Default::default()
+                        };
+                        Command::none()
+                    }
+                }
+            }
+            Message::QuickConnectAuthenticated(result) => {
+                if self.quick_connect.secret.is_empty() {
+                    return Command::none();
+                }
+
+                self.quick_connect = QuickConnect::default();
+
+                if let Panel::Setup(_) = self.panel {
+                    return self.update(Message::SetupAuthenticated(result));
+                }
+
+                match result {
+                    Ok(authentication) => {
+                        self.config.jellyfin.api_key = authentication.access_token;
+                        self.panel = Panel::Settings(Setting::Main);
+                    }
+                    Err(err) => self.quick_connect.error = err,
+                }
+                Command::none()
+            }
+            Message::QuickConnectCancel => {
+                self.quick_connect = QuickConnect::default();
+
+                if let Panel::Settings(_) = self.panel {
+                    self.panel = Panel::Settings(Setting::Main);
+                }
+                Command::none()
+            }
+            Message::Discover => {
+                self.discovering = true;
+                self.servers = Vec::new();
+                Command::perform(discovery::discover(), Message::Discovered)
+            }
+            Message::Discovered(result) => {
+                self.discovering = false;
+                let error = match result {
+                    Ok(servers) if servers.is_empty() => {
+                        "No servers found on the local network".to_string()
+                    }
+                    Ok(servers) => {
+                        self.servers = servers;
+                        return Command::none();
+                    }
+                    Err(err) => err,
+                };
+
+                match self.panel {
+                    Panel::Setup(_) => self.setup.error = error,
+                    _ => self.error = error,
+                }
+                Command::none()
+            }
+            Message::SelectServer(server) => {
+                self.config.jellyfin.url = server.address;
+                self.servers = Vec::new();
+                Command::none()
+            }
+            Message::ToggleMusicField(field, val) => {
+                for (_field, enabled) in &mut self.music_display.fields {
+                    if *_field == field {
+                        *enabled = val;
+                    }
+                }
+                Command::none()
+            }
+            Message::MoveMusicFieldUp(field) => {
+                self.music_display.move_field(field, -1);
+                Command::none()
+            }
+            Message::MoveMusicFieldDown(field) => {
+                self.music_display.move_field(field, 1);
+                Command::none()
+            }
+            Message::UpdateSeparator(separator) => {
+                // Only the last typed character is kept, the separator is a single character
+                if let Some(separator) = separator.chars().last() {
+                    self.music_display.separator = separator;
+                }
+                Command::none()
+            }
+            Message::UpdateNowPlaying(result) => {
+                // Errors here are only cosmetic, the presence loop reports the real ones
+                let now_playing = result.unwrap_or_default();
+
+                self.now_playing_updated = std::time::Instant::now();
+                self.now_playing = now_playing.clone();
+
+                match now_playing {
+                    Some(now_playing)
+                        if self
+                            .cover
+                            .as_ref()
+                            .is_none_or(|(item_id, _)| item_id != &now_playing.item_id) =>
+                    {
+                        Command::perform(
+                            now_playing::get_cover(
+                                self.config.jellyfin.url.clone(),
+                                self.config.jellyfin.api_key.clone(),
+                                now_playing.item_id.clone(),
+                            ),
+                            move |cover| Message::UpdateCover(now_playing.item_id.clone(), cover),
+                        )
+                    }
+                    _ => Command::none(),
+                }
+            }
+            Message::UpdateCover(item_id, result) => {
+                self.cover = result
+                    .ok()
+                    .map(|cover| (item_id, image::Handle::from_memory(cover)));
+                Command::none()
+            }
+            Message::LogLevel(level) => {
+                self.log_level = level;
+                Command::none()
+            }
+            Message::LogFilter(filter) => {
+                self.log_filter = filter;
+                Command::none()
+            }
+            Message::ExportLog(format) => {
+                let dir = std::path::Path::new(&self.config_path)
+                    .parent()
+                    .unwrap_or(std::path::Path::new("."));
+
+                self.log_export = match self.log.export(dir, format) {
+                    Ok(path) => format!("Saved to {}", path.display()),
+                    Err(err) => format!("Export failed: {}", err),
+                };
+                Command::none()
+            }
+            Message::SelectTheme(theme) => {
+                self.gui_config.theme = theme;
+                self.theme = self.gui_config.theme();
+                Command::none()
+            }
+            Message::UpdatePalette(color, value) => {
+                self.gui_config.palette.set(color, value);
+                self.theme = self.gui_config.theme();
+                Command::none()
+            }
+            Message::Tray(event) => match event {
+                TrayEvent::Start => self.update(Message::Start),
+                TrayEvent::Stop => self.update(Message::Stop),
+                TrayEvent::ToggleWindow => {
+                    self.visible = !self.visible;
+                    match self.visible {
+                        true => iced::window::change_mode(iced::window::Mode::Windowed),
+                        false => iced::window::change_mode(iced::window::Mode::Hidden),
+                    }
+                }
+                TrayEvent::Quit if self.has_unsaved_changes() => {
+                    self.visible = true;
+                    self.leaving = Some(Leaving::Quit);
+                    Command::batch([
+                        iced::window::change_mode(iced::window::Mode::Windowed),
+                        iced::window::gain_focus(),
+                    ])
+                }
+                TrayEvent::Quit => iced::window::close(),
+            },
+            Message::CloseRequested => match self.tray {
+                // Keep the presence running in the background when we have a tray to come back to
+                Some(_) => {
+                    self.visible = false;
+                    iced::window::change_mode(iced::window::Mode::Hidden)
+                }
+                None if self.has_unsaved_changes() => {
+                    self.leaving = Some(Leaving::Quit);
+                    Command::none()
+                }
+                None => iced::window::close(),
+            },
         }
     }
 
-    fn subscription(&self) -> iced::Subscription<Self::Message> {
-        iced::time::every(std::time::Duration::from_millis(500)).map(|_| Message::Update)
+    fn subscription(&self) -> iced::Subscription<Self::Message> {
+        let close_requested = iced::subscription::events_with(|event, _| match event {
+            iced::Event::Window(iced::window::Event::CloseRequested) => {
+                Some(Message::CloseRequested)
+            }
+            _ => None,
+        });
+
+        let tray = iced::subscription::channel(
+            std::any::TypeId::of::<Tray>(),
+            10,
+            |mut output| async move {
+                loop {
+                    if let Some(event) = tray::next_event().await {
+                        output.send(Message::Tray(event)).await.ok();
+                    }
+                }
+            },
+        );
+
+        let rx = self.rx.clone();
+
+        let events = iced::subscription::channel(
+            std::any::TypeId::of::<Event>(),
+            100,
+            |mut output| async move {
+                loop {
+                    let rx = rx.clone();
+
+                    match tokio::task::spawn_blocking(move || rx.lock().unwrap().recv()).await {
+                        Ok(Ok(event)) => {
+                            output.send(Message::Event(Entry::from(event))).await.ok();
+                        }
+                        // The server dropped its sender, so nothing else is ever going to arrive
+                        _ => std::future::pending::<()>().await,
+                    }
+                }
+            },
+        );
+
+        let ipc_status = self.ipc_status.clone();
+        let listener = self.listener.clone();
+
+        let control = iced::subscription::channel(
+            std::any::TypeId::of::<Request>(),
+            10,
+            |output| async move {
+                let listener = listener.lock().unwrap().take();
+                let listener = match listener {
+                    Some(listener) => listener,
+                    None => std::future::pending().await,
+                };
+
+                if let Err(err) = ipc::listen(listener, ipc_status, move |request| {
+                    output
+                        .clone()
+                        .try_send(Message::Ipc(request))
+                        .map_err(|err| err.to_string())
+                })
+                .await
+                {
+                    eprintln!("Control socket unavailable: {}", err);
+                }
+
+                std::future::pending().await
+            },
+        );
+
+        let config_path = self.config_path.clone();
+
+        let watcher = iced::subscription::channel(
+            (std::any::TypeId::of::<ConfigWatcher>(), config_path.clone()),
+            10,
+            |mut output| async move {
+                match ConfigWatcher::new(&config_path) {
+                    Ok(mut watcher) => {
+                        while watcher.changed().await.is_some() {
+                            output.send(Message::ConfigChanged).await.ok();
+                        }
+                    }
+                    Err(err) => eprintln!("Couldn't watch {}: {:?}", config_path, err),
+                }
+
+                std::future::pending().await
+            },
+        );
+
+        let tick = match self
+            .now_playing
+            .as_ref()
+            .is_some_and(|now_playing| !now_playing.paused)
+        {
+            true => iced::time::every(std::time::Duration::from_secs(1)).map(|_| Message::Tick),
+            false => iced::Subscription::none(),
+        };
+
+        iced::Subscription::batch(vec![events, control, watcher, tick, close_requested, tray])
     }
 
     fn theme(&self) -> Self::Theme {
-        Theme::Dark
+        self.theme.clone()
     }
 
     fn view(&self) -> Element<Message> {
@@ -504,6 +1988,9 @@ impl Application for Gui {
                 let start_stop = row![
                     button("Start").on_press(Message::Start).padding(10),
                     button("Stop").on_press(Message::Stop).padding(10),
+                    button("Restart service")
+                        .on_press(Message::Restart)
+                        .padding(10),
                 ]
                 .spacing(10)
                 .align_items(Alignment::Center);
@@ -512,13 +1999,38 @@ impl Application for Gui {
                     .spacing(10)
                     .align_items(Alignment::Center);
 
-                let settings = button("Settings")
-                    .on_press(Message::Open(Panel::Settings(Setting::Main)))
-                    .padding(5);
+                let settings = row![
+                    button("Settings")
+                        .on_press(Message::Open(Panel::Settings(Setting::Main)))
+                        .padding(5),
+                    button("Log").on_press(Message::Open(Panel::Log)).padding(5),
+                ]
+                .spacing(3)
+                .align_items(Alignment::Center);
 
-                column![start_stop, status, error, settings]
-                    .spacing(10)
-                    .align_items(Alignment::Center)
+                let discord = match self.discord_connected {
+                    true => text("Discord: connected"),
+                    false => text("Discord: disconnected"),
+                };
+
+                let crashes = match self.crashes {
+                    0 => text(""),
+                    crashes => text(format!("Crashes: {}", crashes)),
+                };
+
+                column![
+                    self.config_error_view(),
+                    start_stop,
+                    status,
+                    text(self.notice.clone()),
+                    discord,
+                    crashes,
+                    self.now_playing_view(),
+                    error,
+                    settings
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center)
             }
             Panel::Settings(setting) => match setting {
                 Setting::Main => {
@@ -552,6 +2064,21 @@ impl Application for Gui {
                                 .padding(5),
                         ]
                         .spacing(3)
+                        .align_items(Alignment::Start),
+                        row![
+                            button("Music >")
+                                .on_press(Message::Open(Panel::Settings(Setting::Music)))
+                                .padding(5),
+                            button("Appearance >")
+                                .on_press(Message::Open(Panel::Settings(Setting::Appearance)))
+                                .padding(5),
+                        ]
+                        .spacing(3)
+                        .align_items(Alignment::Start),
+                        row![button("Sessions >")
+                            .on_press(Message::Open(Panel::Settings(Setting::Sessions)))
+                            .padding(5),]
+                        .spacing(3)
                         .align_items(Alignment::Start)
                     ]
                     .spacing(3)
@@ -561,10 +2088,16 @@ impl Application for Gui {
                         .on_press(Message::ReloadConfig)
                         .padding(10);
 
-                    let url = row![
-                        text("URL:"),
-                        text_input("http://localhost:8096", &self.config.jellyfin.url)
-                            .on_input(Message::UpdateUrl),
+                    let url = column![
+                        row![
+                            text("URL:"),
+                            text_input("http://localhost:8096", &self.config.jellyfin.url)
+                                .on_input(Message::UpdateUrl),
+                            button("Discover").on_press(Message::Discover),
+                        ]
+                        .spacing(3)
+                        .align_items(Alignment::Center),
+                        self.servers_view(),
                     ]
                     .spacing(3)
                     .align_items(Alignment::Center);
@@ -578,19 +2111,31 @@ impl Application for Gui {
                     .spacing(3)
                     .align_items(Alignment::Center);
 
+                    let quick_connect = button("Sign in with Quick Connect")
+                        .on_press(Message::QuickConnectStart)
+                        .padding(5);
+
                     let application_id = row![
                         text("Application ID:"),
                         text_input("1053747938519679018", &self.application_id)
                             .on_input(Message::UpdateApplicationId)
                     ];
 
-                    let save = button("Save").on_press(Message::SaveSettings).padding(10);
+                    // Saving would overwrite the broken file with whatever loaded last
+                    let save = match self.config_error {
+                        Some(_) => button("Save").padding(10),
+                        None => button("Save").on_press(Message::SaveSettings).padding(10),
+                    };
 
                     column![
+                        self.config_error_view(),
                         menu_buttons,
                         reload_config,
+                        self.backups_view(),
                         url,
                         api_key,
+                        self.connection_test_view(),
+                        quick_connect,
                         application_id,
                         save,
                         status
@@ -690,6 +2235,29 @@ impl Application for Gui {
                         .spacing(10)
                         .align_items(Alignment::Center)
                 }
+                Setting::Sessions => {
+                    let back = row![
+                        button("< Back")
+                            .on_press(Message::Open(Panel::Settings(Setting::Main)))
+                            .padding(5),
+                        button("Refresh")
+                            .on_press(Message::RefreshSessions)
+                            .padding(5),
+                    ]
+                    .spacing(3)
+                    .align_items(Alignment::Center);
+
+                    column![
+                        back,
+                        text("Pinned sessions are the only ones shown, excluded ones are never shown")
+                            .size(12),
+                        text("Discord follows these within a few seconds. When the same user plays on an allowed and an excluded session at once, only the title and progress of the allowed one are shown.")
+                            .size(12),
+                        scrollable(self.sessions_view()).height(Length::Fill),
+                    ]
+                    .spacing(10)
+                    .align_items(Alignment::Center)
+                }
                 Setting::Users => {
                     let back = row![button("< Back")
                         .on_press(Message::Open(Panel::Settings(Setting::Main)))
@@ -705,38 +2273,32 @@ impl Application for Gui {
                     ]
                     .align_items(Alignment::Center);
 
-                    let usernames = match &self.config.jellyfin.username {
-                        Username::Vec(usernames) => usernames.iter().fold(
-                            column![text("Usernames:")]
-                                .spacing(4)
-                                .align_items(Alignment::Start),
-                            |column: iced::widget::Column<'_, Message>, username| {
-                                column.push(
-                                    row![
-                                        button("X").on_press(Message::RemoveUsername(
-                                            username.to_string()
-                                        )),
-                                        text(username),
-                                    ]
-                                    .spacing(3)
-                                    .align_items(Alignment::Center),
-                                )
-                            },
-                        ),
-                        Username::String(username) => column![
-                            text("Usernames:"),
-                            row![
-                                button("X").on_press(Message::RemoveUsername(username.to_string())),
-                                text(username),
-                            ]
-                            .spacing(3)
-                            .align_items(Alignment::Center),
-                        ]
-                        .spacing(4)
-                        .align_items(Alignment::Start),
-                    };
+                    let usernames = self.gui_config.users.iter().fold(
+                        column![text("Tracked users, highest priority first:")]
+                            .spacing(4)
+                            .align_items(Alignment::Start),
+                        |column: iced::widget::Column<'_, Message>, user| {
+                            let name = user.name.clone();
+
+                            column.push(
+                                row![
+                                    button("X")
+                                        .on_press(Message::RemoveUsername(user.name.clone())),
+                                    button("^")
+                                        .on_press(Message::MoveUsernameUp(user.name.clone())),
+                                    button("v")
+                                        .on_press(Message::MoveUsernameDown(user.name.clone())),
+                                    checkbox(&user.name, user.enabled, move |val| {
+                                        Message::ToggleUserEnabled(name.clone(), val)
+                                    }),
+                                ]
+                                .spacing(3)
+                                .align_items(Alignment::Center),
+                            )
+                        },
+                    );
 
-                    column![back, add, usernames]
+                    column![back, self.server_users_view(), add, usernames]
                         .spacing(10)
                         .align_items(Alignment::Center)
                 }
@@ -785,21 +2347,406 @@ impl Application for Gui {
                             .spacing(4)
                             .align_items(Alignment::Start),
                         |column: iced::widget::Column<'_, Message>, library| {
+                            let details = match (&library.collection_type, library.item_count) {
+                                (Some(kind), Some(count)) => format!("{}, {} items", kind, count),
+                                (Some(kind), None) => kind.to_string(),
+                                (None, Some(count)) => format!("{} items", count),
+                                (None, None) => String::new(),
+                            };
+
+                            column.push(
+                                row![
+                                    checkbox(&library.name, library.enabled, |val| {
+                                        Message::ToggleLibrary(library.to_owned(), val)
+                                    }),
+                                    text(details).size(12),
+                                ]
+                                .spacing(6)
+                                .align_items(Alignment::Center),
+                            )
+                        },
+                    );
+
+                    // Blacklisted libraries the server doesn't have (anymore)
+                    let stale = self
+                        .gui_config
+                        .blacklisted_libraries
+                        .iter()
+                        .filter(|blacklisted| {
+                            !self
+                                .libraries
+                                .iter()
+                                .any(|library| library.id == blacklisted.id)
+                        })
+                        .fold(
+                            column![].spacing(4).align_items(Alignment::Start),
+                            |column: iced::widget::Column<'_, Message>, library| {
+                                column.push(
+                                    row![
+                                        button("X").on_press(Message::RemoveStaleLibrary(
+                                            library.name.clone()
+                                        )),
+                                        text(format!("{} (stale)", library.name)),
+                                    ]
+                                    .spacing(3)
+                                    .align_items(Alignment::Center),
+                                )
+                            },
+                        );
+
+                    // Until the server answered, everything would look stale
+                    let stale = match self.libraries.is_empty() {
+                        true => column![],
+                        false => stale,
+                    };
+
+                    let error = match self.libraries_error.is_empty() {
+                        true => text(""),
+                        false => text(format!(
+                            "Couldn't get the libraries: {}",
+                            self.libraries_error
+                        ))
+                        .style(self.theme.palette().danger),
+                    };
+
+                    column![back, error, libraries, stale]
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                }
+                Setting::Music => {
+                    let back = row![button("< Back")
+                        .on_press(Message::Open(Panel::Settings(Setting::Main)))
+                        .padding(5),]
+                    .spacing(3)
+                    .align_items(Alignment::Center);
+
+                    let fields = self.music_display.fields.iter().fold(
+                        column![text("What to display:")]
+                            .spacing(4)
+                            .align_items(Alignment::Start),
+                        |column: iced::widget::Column<'_, Message>, (field, enabled)| {
                             column.push(
-                                row![checkbox(&library.name, library.enabled, |val| {
-                                    Message::ToggleLibrary(library.to_owned(), val)
-                                }),]
+                                row![
+                                    button("^").on_press(Message::MoveMusicFieldUp(*field)),
+                                    button("v").on_press(Message::MoveMusicFieldDown(*field)),
+                                    checkbox(field.label(), *enabled, |val| {
+                                        Message::ToggleMusicField(*field, val)
+                                    }),
+                                ]
                                 .spacing(3)
                                 .align_items(Alignment::Center),
                             )
                         },
                     );
 
-                    column![back, libraries]
+                    let separator = row![
+                        text("Separator:"),
+                        text_input("-", &self.music_display.separator.to_string())
+                            .on_input(Message::UpdateSeparator),
+                    ]
+                    .spacing(3)
+                    .align_items(Alignment::Center);
+
+                    let preview = column![
+                        text("Preview:").size(20),
+                        text(MusicDisplay::SAMPLE_TITLE),
+                        text(self.music_display.preview()),
+                    ]
+                    .spacing(3)
+                    .align_items(Alignment::Center);
+
+                    column![back, fields, separator, preview]
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                }
+                Setting::Appearance => {
+                    let back = row![button("< Back")
+                        .on_press(Message::Open(Panel::Settings(Setting::Main)))
+                        .padding(5),]
+                    .spacing(3)
+                    .align_items(Alignment::Center);
+
+                    let selected = Some(self.gui_config.theme);
+
+                    let themes = column![
+                        radio("Light", ThemeChoice::Light, selected, Message::SelectTheme),
+                        radio("Dark", ThemeChoice::Dark, selected, Message::SelectTheme),
+                        radio(
+                            "Follow system",
+                            ThemeChoice::System,
+                            selected,
+                            Message::SelectTheme
+                        ),
+                        radio(
+                            "Custom",
+                            ThemeChoice::Custom,
+                            selected,
+                            Message::SelectTheme
+                        ),
+                    ]
+                    .spacing(6)
+                    .align_items(Alignment::Start);
+
+                    let palette = match self.gui_config.theme {
+                        ThemeChoice::Custom => [
+                            ("Background:", PaletteColor::Background),
+                            ("Text:", PaletteColor::Text),
+                            ("Primary:", PaletteColor::Primary),
+                            ("Success:", PaletteColor::Success),
+                            ("Danger:", PaletteColor::Danger),
+                        ]
+                        .into_iter()
+                        .fold(
+                            column![].spacing(4).align_items(Alignment::Start),
+                            |column: iced::widget::Column<'_, Message>, (label, color)| {
+                                column.push(
+                                    row![
+                                        text(label),
+                                        text_input("#000000", self.gui_config.palette.get(color))
+                                            .on_input(move |value| {
+                                                Message::UpdatePalette(color, value)
+                                            }),
+                                    ]
+                                    .spacing(3)
+                                    .align_items(Alignment::Center),
+                                )
+                            },
+                        ),
+                        _ => column![],
+                    };
+
+                    column![back, themes, palette]
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                }
+                Setting::QuickConnect => {
+                    let back = row![button("< Back")
+                        .on_press(Message::QuickConnectCancel)
+                        .padding(5),]
+                    .spacing(3)
+                    .align_items(Alignment::Center);
+
+                    column![back, self.quick_connect_view()]
                         .spacing(10)
                         .align_items(Alignment::Center)
                 }
             },
+            Panel::Log => {
+                let back = row![button("< Back")
+                    .on_press(Message::Open(Panel::Main))
+                    .padding(5),]
+                .spacing(3)
+                .align_items(Alignment::Center);
+
+                let filter = row![
+                    pick_list(&Level::ALL[..], Some(self.log_level), Message::LogLevel),
+                    text_input("Filter", &self.log_filter).on_input(Message::LogFilter),
+                ]
+                .spacing(3)
+                .align_items(Alignment::Center);
+
+                let entries = self.log.filtered(self.log_level, &self.log_filter).fold(
+                    column![].spacing(2).align_items(Alignment::Start),
+                    |column: iced::widget::Column<'_, Message>, entry| {
+                        column.push(text(entry.to_string()).size(12))
+                    },
+                );
+
+                let export = row![
+                    button("Export .log")
+                        .on_press(Message::ExportLog(event_log::Format::Text))
+                        .padding(5),
+                    button("Export .jsonl")
+                        .on_press(Message::ExportLog(event_log::Format::JsonLines))
+                        .padding(5),
+                ]
+                .spacing(3)
+                .align_items(Alignment::Center);
+
+                column![
+                    back,
+                    filter,
+                    scrollable(entries).height(Length::Fill),
+                    export,
+                    text(self.log_export.clone()).size(12),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center)
+            }
+            Panel::Repair => {
+                let back = row![button("< Back")
+                    .on_press(Message::Open(Panel::Main))
+                    .padding(5),]
+                .spacing(3)
+                .align_items(Alignment::Center);
+
+                column![
+                    back,
+                    self.repair_view(),
+                    self.backups_view(),
+                    button("Reload Config")
+                        .on_press(Message::ReloadConfig)
+                        .padding(10),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center)
+            }
+            Panel::Setup(step) => {
+                let title = column![
+                    text("Setup").size(30),
+                    text(format!("Step {}/4", step.number())),
+                ]
+                .align_items(Alignment::Center);
+
+                let form = match step {
+                    Step::Server => {
+                        let next = button("Next >").padding(5);
+                        let next = match self.setup.busy || self.config.jellyfin.url.is_empty() {
+                            true => next,
+                            false => next.on_press(Message::SetupCheckServer),
+                        };
+
+                        column![
+                            text("Where is your Jellyfin server?"),
+                            text_input("http://localhost:8096", &self.config.jellyfin.url)
+                                .on_input(Message::UpdateUrl)
+                                .on_submit(Message::SetupCheckServer),
+                            self.servers_view(),
+                            row![
+                                button("Use API key")
+                                    .on_press(Message::Open(Panel::Settings(Setting::Main)))
+                                    .padding(5),
+                                button("Discover").on_press(Message::Discover).padding(5),
+                                next,
+                            ]
+                            .spacing(3)
+                            .align_items(Alignment::Center),
+                        ]
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                    }
+                    Step::Credentials => {
+                        let login = button("Sign in").padding(5);
+                        let login = match self.setup.busy || self.setup.username.is_empty() {
+                            true => login,
+                            false => login.on_press(Message::SetupLogin),
+                        };
+
+                        column![
+                            text(format!("Sign in to {}", self.setup.server_name)),
+                            row![
+                                text("Username:"),
+                                text_input("Username", &self.setup.username)
+                                    .on_input(Message::SetupUsername),
+                            ]
+                            .spacing(3)
+                            .align_items(Alignment::Center),
+                            row![
+                                text("Password:"),
+                                text_input("Password", &self.setup.password)
+                                    .password()
+                                    .on_input(Message::SetupPassword)
+                                    .on_submit(Message::SetupLogin),
+                            ]
+                            .spacing(3)
+                            .align_items(Alignment::Center),
+                            row![
+                                button("< Back")
+                                    .on_press(Message::Open(Panel::Setup(Step::Server)))
+                                    .padding(5),
+                                login,
+                                button("Quick Connect")
+                                    .on_press(Message::QuickConnectStart)
+                                    .padding(5),
+                            ]
+                            .spacing(3)
+                            .align_items(Alignment::Center),
+                            self.quick_connect_view(),
+                        ]
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                    }
+                    Step::Users => {
+                        let users = self.setup.users.iter().fold(
+                            column![text("Which users should be tracked?")]
+                                .spacing(4)
+                                .align_items(Alignment::Start),
+                            |column: iced::widget::Column<'_, Message>, username| {
+                                column.push(checkbox(
+                                    username,
+                                    self.setup.selected_users.contains(username),
+                                    |val| Message::SetupToggleUser(username.to_string(), val),
+                                ))
+                            },
+                        );
+
+                        let next = button("Next >").padding(5);
+                        let next = match self.setup.busy || self.setup.selected_users.is_empty() {
+                            true => next,
+                            false => next.on_press(Message::Open(Panel::Setup(Step::Discord))),
+                        };
+
+                        column![
+                            users,
+                            row![
+                                button("< Back")
+                                    .on_press(Message::Open(Panel::Setup(Step::Credentials)))
+                                    .padding(5),
+                                next,
+                            ]
+                            .spacing(3)
+                            .align_items(Alignment::Center),
+                        ]
+                        .spacing(10)
+                        .align_items(Alignment::Center)
+                    }
+                    Step::Discord => column![
+                        text("Discord Application ID:"),
+                        text_input("1053747938519679018", &self.application_id)
+                            .on_input(Message::UpdateApplicationId),
+                        row![
+                            button("< Back")
+                                .on_press(Message::Open(Panel::Setup(Step::Users)))
+                                .padding(5),
+                            button("Finish").on_press(Message::SetupFinish).padding(5),
+                        ]
+                        .spacing(3)
+                        .align_items(Alignment::Center),
+                    ]
+                    .spacing(10)
+                    .align_items(Alignment::Center),
+                };
+
+                column![title, form, text(self.setup.error.clone())]
+                    .spacing(10)
+                    .align_items(Alignment::Center)
+            }
+        };
+
+        let content = match (&self.leaving, &self.panel) {
+            (Some(_), _) => column![
+                text("Unsaved changes").size(30),
+                text("Save them before leaving?"),
+                row![
+                    button("Save").on_press(Message::SaveAndLeave).padding(10),
+                    button("Discard")
+                        .on_press(Message::DiscardChanges)
+                        .padding(10),
+                    button("Cancel").on_press(Message::CancelLeave).padding(10),
+                ]
+                .spacing(10)
+                .align_items(Alignment::Center),
+                text(self.error.clone()),
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+            (None, Panel::Settings(_)) if self.has_unsaved_changes() => column![
+                text("● Unsaved changes").style(self.theme.palette().primary),
+                content
+            ]
+            .spacing(10)
+            .align_items(Alignment::Center),
+            (None, _) => content,
         };
 
         container(content)
@@ -883,24 +2830,150 @@ pub struct ImageOptions {
     imgur_client_id: String,
 }
 
-async fn get_libraries(url: String, api_key: String) -> Result<Vec<String>, reqwest::Error> {
-    let media_folders: Value = reqwest::get(format!(
-        "{}/Library/MediaFolders?api_key={}",
-        url.trim_end_matches('/'),
-        api_key
-    ))
-    .await?
-    .json()
-    .await?;
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MusicField {
+    Artist,
+    Album,
+    Genres,
+    Year,
+    Track,
+}
+
+impl MusicField {
+    const ALL: [MusicField; 5] = [
+        MusicField::Artist,
+        MusicField::Album,
+        MusicField::Genres,
+        MusicField::Year,
+        MusicField::Track,
+    ];
+
+    /// The name jellyfin-rpc uses for this field in `music.display`
+    fn key(&self) -> &'static str {
+        match self {
+            MusicField::Artist => "artist",
+            MusicField::Album => "album",
+            MusicField::Genres => "genres",
+            MusicField::Year => "year",
+            MusicField::Track => "track",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            MusicField::Artist => "Artist",
+            MusicField::Album => "Album",
+            MusicField::Genres => "Genres",
+            MusicField::Year => "Year",
+            MusicField::Track => "Track number",
+        }
+    }
+
+    fn sample(&self) -> &'static str {
+        match self {
+            MusicField::Artist => "By Sample Artist",
+            MusicField::Album => "Sample Album",
+            MusicField::Genres => "Rock, Pop",
+            MusicField::Year => "2023",
+            MusicField::Track => "Track 3",
+        }
+    }
+}
+
+pub struct MusicDisplay {
+    fields: Vec<(MusicField, bool)>,
+    separator: char,
+}
+
+impl Default for MusicDisplay {
+    fn default() -> Self {
+        Self {
+            fields: MusicField::ALL
+                .iter()
+                .map(|field| {
+                    (
+                        *field,
+                        matches!(field, MusicField::Artist | MusicField::Genres),
+                    )
+                })
+                .collect(),
+            separator: '-',
+        }
+    }
+}
+
+impl MusicDisplay {
+    const SAMPLE_TITLE: &'static str = "Sample Song";
+
+    fn update(&mut self, config: &Config) {
+        *self = Self::default();
+
+        let music = match &config.jellyfin.music {
+            Some(music) => music,
+            None => return,
+        };
+
+        if let Some(separator) = music.separator {
+            self.separator = separator;
+        }
+
+        let display: Vec<String> = match &music.display {
+            Some(Display::Vec(display)) => display.to_vec(),
+            Some(Display::String(display)) => display
+                .split(',')
+                .map(|key| key.trim().to_string())
+                .collect(),
+            None => return,
+        };
+
+        // Enabled fields come first in the order they're configured, the rest are appended
+        let mut fields: Vec<(MusicField, bool)> = display
+            .iter()
+            .filter_map(|key| {
+                MusicField::ALL
+                    .into_iter()
+                    .find(|field| field.key() == key.as_str())
+            })
+            .map(|field| (field, true))
+            .collect();
+
+        for field in MusicField::ALL {
+            if !fields.iter().any(|(_field, _)| *_field == field) {
+                fields.push((field, false));
+            }
+        }
+
+        self.fields = fields;
+    }
 
-    let items: Vec<Value> = media_folders["Items"].as_array().unwrap().to_vec();
+    fn move_field(&mut self, field: MusicField, offset: isize) {
+        if let Some(index) = self.fields.iter().position(|(_field, _)| *_field == field) {
+            let target = index as isize + offset;
+            if target >= 0 && (target as usize) < self.fields.len() {
+                self.fields.swap(index, target as usize);
+            }
+        }
+    }
 
-    let mut libraries: Vec<String> = Vec::new();
+    fn display(&self) -> Vec<String> {
+        self.fields
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(field, _)| field.key().to_string())
+            .collect()
+    }
 
-    for library in items {
-        let name = library["Name"].as_str().unwrap().to_string();
-        libraries.push(name);
+    /// The second line Discord will show for a sample track
+    fn preview(&self) -> String {
+        self.fields
+            .iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(field, _)| field.sample())
+            .collect::<Vec<&str>>()
+            .join(format!(" {} ", self.separator).as_str())
     }
+}
 
-    Ok(libraries)
+fn write_config(config_path: &str, config: &Config, gui_config: &GuiConfig) -> std::io::Result<()> {
+    config_file::write(config_path, &gui_config::merge(config, gui_config)?)
 }